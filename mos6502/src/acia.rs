@@ -0,0 +1,387 @@
+use crate::mem::{Device, Memory};
+use crate::types::*;
+use std::cell::Cell;
+
+const REG_DATA: Word = 0x00;
+const REG_STATUS: Word = 0x01; // writing to it does a programmed reset
+const REG_COMMAND: Word = 0x02;
+const REG_CONTROL: Word = 0x03;
+
+const ST_PARITY_ERROR: Byte = 0b0000_0001;
+const ST_FRAMING_ERROR: Byte = 0b0000_0010;
+const ST_OVERRUN: Byte = 0b0000_0100;
+const ST_RX_FULL: Byte = 0b0000_1000;
+const ST_TX_EMPTY: Byte = 0b0001_0000;
+const ST_IRQ: Byte = 0b1000_0000;
+
+const CMD_DTR: Byte = 0b0000_0001; // receiver and interrupts are enabled
+const CMD_RX_IRQ_DISABLE: Byte = 0b0000_0010;
+const CMD_TX_CONTROL: Byte = 0b0000_1100;
+const CMD_ECHO: Byte = 0b0001_0000;
+const CMD_PARITY: Byte = 0b0010_0000; // parity bit is sent
+
+const TX_OFF: Byte = 0b0000_0000; // RTS high, transmitter off
+const TX_IRQ: Byte = 0b0000_0100; // RTS low, transmit interrupt enabled
+
+const CTL_BAUD_RATE: Byte = 0b0000_1111;
+const CTL_WORD_LENGTH: Byte = 0b0110_0000;
+const CTL_STOP_BITS: Byte = 0b1000_0000;
+
+// Baud rates selected by the lower bits of the control register. Zero means the external
+// receiver clock, which has no fixed rate, so characters are transferred without delay.
+const BAUD_RATES: [f32; 16] = [
+    0.0, 50.0, 75.0, 109.92, 134.58, 150.0, 300.0, 600.0, 1200.0, 1800.0, 2400.0, 3600.0, 4800.0,
+    7200.0, 9600.0, 19200.0,
+];
+
+// The other end of the serial line. Receiving must never block, since it is called
+// from the emulation loop: if nothing has arrived yet, it just returns None.
+pub trait Serial {
+    fn receive(&mut self) -> Option<Byte>;
+    fn transmit(&mut self, data: Byte);
+}
+
+// MOS 6551 Asynchronous Communications Interface Adapter. It has four registers: transmit/receive
+// data, status, command and control. Characters are shifted in and out at the baud rate set in
+// the control register, which is converted into CPU cycles using the given CPU frequency.
+// The IRQ line is pulled when a character has been received or the transmitter is ready
+// for the next one (if enabled in the command register) and released by reading the status.
+pub struct Acia {
+    port: Box<dyn Serial + Send>,
+    cpu_frequency: f32, // MHz
+
+    status: Cell<Byte>,
+    command: Byte,
+    control: Byte,
+
+    rx_data: Byte,
+    rx_timer: u64,
+
+    tx_data: Option<Byte>,
+    tx_shift: Option<Byte>,
+    tx_timer: u64,
+}
+
+impl Acia {
+    pub fn new(port: Box<dyn Serial + Send>, cpu_frequency: f32) -> Acia {
+        let mut acia = Acia {
+            port,
+            cpu_frequency,
+            status: Cell::new(0),
+            command: 0,
+            control: 0,
+            rx_data: 0,
+            rx_timer: 0,
+            tx_data: None,
+            tx_shift: None,
+            tx_timer: 0,
+        };
+        acia.reset();
+        acia
+    }
+
+    // Hardware reset, as done by the RES pin.
+    pub fn reset(&mut self) {
+        self.status.set(ST_TX_EMPTY);
+        self.command = CMD_RX_IRQ_DISABLE;
+        self.control = 0;
+        self.tx_data = None;
+        self.tx_shift = None;
+    }
+
+    // Reset done by writing to the status register. It only clears the lower bits of the
+    // command register and the overrun flag, the control register stays intact.
+    fn programmed_reset(&mut self) {
+        self.command &= !(CMD_ECHO | CMD_TX_CONTROL | CMD_RX_IRQ_DISABLE | CMD_DTR);
+        self.command |= CMD_RX_IRQ_DISABLE;
+        self.clear_status(ST_OVERRUN);
+    }
+
+    // Number of CPU cycles it takes to shift a whole character: start bit, data bits,
+    // parity and stop bits.
+    fn frame_cycles(&self) -> u64 {
+        let baud = BAUD_RATES[(self.control & CTL_BAUD_RATE) as usize];
+        if baud == 0.0 {
+            return 0;
+        }
+        let data_bits = 8 - ((self.control & CTL_WORD_LENGTH) >> 5) as u32;
+        let parity_bits = (self.command & CMD_PARITY != 0) as u32;
+        let stop_bits = if self.control & CTL_STOP_BITS != 0 {
+            2
+        } else {
+            1
+        };
+        let bits = 1 + data_bits + parity_bits + stop_bits;
+        (self.cpu_frequency as f64 * 1_000_000.0 * bits as f64 / baud as f64) as u64
+    }
+
+    fn word_mask(&self) -> Byte {
+        0xFF >> ((self.control & CTL_WORD_LENGTH) >> 5)
+    }
+
+    fn set_status(&self, bits: Byte) {
+        self.status.set(self.status.get() | bits);
+    }
+
+    fn clear_status(&self, bits: Byte) {
+        self.status.set(self.status.get() & !bits);
+    }
+
+    fn enabled(&self) -> bool {
+        self.command & CMD_DTR != 0
+    }
+
+    fn tx_control(&self) -> Byte {
+        self.command & CMD_TX_CONTROL
+    }
+
+    fn receive(&mut self) {
+        if !self.enabled() {
+            return;
+        }
+        let data = match self.port.receive() {
+            Some(data) => data & self.word_mask(),
+            None => return,
+        };
+        self.rx_timer = self.frame_cycles();
+
+        // The echo mode retransmits everything received, bypassing the transmitter.
+        if self.command & CMD_ECHO != 0 && self.tx_control() == TX_OFF {
+            self.port.transmit(data);
+        }
+
+        // A character that arrives before the previous one was read is lost.
+        if self.status.get() & ST_RX_FULL != 0 {
+            self.set_status(ST_OVERRUN);
+            return;
+        }
+        self.rx_data = data;
+        self.set_status(ST_RX_FULL);
+        if self.command & CMD_RX_IRQ_DISABLE == 0 {
+            self.set_status(ST_IRQ);
+        }
+    }
+
+    fn transmit(&mut self, cycles: u64) {
+        if let Some(data) = self.tx_shift {
+            self.tx_timer = self.tx_timer.saturating_sub(cycles);
+            if self.tx_timer == 0 {
+                self.port.transmit(data);
+                self.tx_shift = None;
+            }
+        }
+
+        if self.tx_shift.is_none() && self.tx_control() != TX_OFF {
+            if let Some(data) = self.tx_data.take() {
+                self.tx_shift = Some(data & self.word_mask());
+                self.tx_timer = self.frame_cycles();
+                self.set_status(ST_TX_EMPTY);
+                if self.enabled() && self.tx_control() == TX_IRQ {
+                    self.set_status(ST_IRQ);
+                }
+            }
+        }
+    }
+}
+
+impl Memory for Acia {
+    fn read(&self, addr: Word) -> Byte {
+        match addr & 0x03 {
+            REG_DATA => {
+                self.clear_status(ST_RX_FULL | ST_OVERRUN | ST_FRAMING_ERROR | ST_PARITY_ERROR);
+                self.rx_data
+            }
+            REG_STATUS => {
+                let status = self.status.get();
+                self.clear_status(ST_IRQ);
+                status
+            }
+            REG_COMMAND => self.command,
+            REG_CONTROL => self.control,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        match addr & 0x03 {
+            REG_DATA => {
+                self.tx_data = Some(data);
+                self.clear_status(ST_TX_EMPTY);
+            }
+            REG_STATUS => self.programmed_reset(),
+            REG_COMMAND => self.command = data,
+            REG_CONTROL => self.control = data,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Device for Acia {
+    fn tick(&mut self, cycles: u64) {
+        self.rx_timer = self.rx_timer.saturating_sub(cycles);
+        if self.rx_timer == 0 {
+            self.receive();
+        }
+        self.transmit(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.status.get() & ST_IRQ != 0
+    }
+}
+
+#[cfg(test)]
+mod acia_test {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Line {
+        rx: Arc<Mutex<VecDeque<Byte>>>,
+        tx: Arc<Mutex<Vec<Byte>>>,
+    }
+
+    impl Serial for Line {
+        fn receive(&mut self) -> Option<Byte> {
+            self.rx.lock().unwrap().pop_front()
+        }
+
+        fn transmit(&mut self, data: Byte) {
+            self.tx.lock().unwrap().push(data);
+        }
+    }
+
+    // 9600 baud, 8N1 with a 1 MHz CPU: 10 bits of 104.17 cycles each.
+    const FRAME: u64 = 1041;
+
+    fn setup() -> (Acia, Line) {
+        let line = Line::default();
+        let mut acia = Acia::new(Box::new(line.clone()), 1.0);
+        acia.write(REG_CONTROL, 0b0001_1110); // 9600 baud, 8 bits, 1 stop bit
+        acia.write(REG_COMMAND, 0b0000_1001); // DTR, rx irq enabled, tx irq disabled
+        (acia, line)
+    }
+
+    #[test]
+    fn reset_state() {
+        let acia = Acia::new(Box::new(Line::default()), 1.0);
+        assert_eq!(acia.read(REG_STATUS), ST_TX_EMPTY);
+        assert_eq!(acia.read(REG_COMMAND), CMD_RX_IRQ_DISABLE);
+        assert_eq!(acia.read(REG_CONTROL), 0);
+    }
+
+    #[test]
+    fn transmit_at_baud_rate() {
+        let (mut acia, line) = setup();
+        assert_eq!(acia.frame_cycles(), FRAME);
+
+        acia.write(REG_DATA, b'A');
+        assert_eq!(acia.read(REG_STATUS) & ST_TX_EMPTY, 0);
+
+        // the byte moves into the shift register, so the next one can be written
+        acia.tick(1);
+        assert_eq!(acia.read(REG_STATUS) & ST_TX_EMPTY, ST_TX_EMPTY);
+        acia.write(REG_DATA, b'B');
+
+        acia.tick(FRAME - 1);
+        assert!(line.tx.lock().unwrap().is_empty());
+        acia.tick(1);
+        assert_eq!(*line.tx.lock().unwrap(), b"A");
+
+        acia.tick(1);
+        acia.tick(FRAME);
+        assert_eq!(*line.tx.lock().unwrap(), b"AB");
+    }
+
+    #[test]
+    fn transmitter_off() {
+        let (mut acia, line) = setup();
+        acia.write(REG_COMMAND, CMD_DTR); // RTS high, transmitter off
+        acia.write(REG_DATA, b'A');
+        acia.tick(FRAME * 2);
+        assert!(line.tx.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn receive_irq() {
+        let (mut acia, line) = setup();
+        line.rx.lock().unwrap().extend(b"hi");
+
+        acia.tick(1);
+        assert!(acia.irq());
+        assert_eq!(acia.read(REG_STATUS), ST_IRQ | ST_RX_FULL | ST_TX_EMPTY);
+        assert!(!acia.irq());
+        assert_eq!(acia.read(REG_DATA), b'h');
+        assert_eq!(acia.read(REG_STATUS), ST_TX_EMPTY);
+
+        // the next character takes a whole frame to arrive
+        acia.tick(FRAME - 1);
+        assert!(!acia.irq());
+        acia.tick(1);
+        assert!(acia.irq());
+        assert_eq!(acia.read(REG_DATA), b'i');
+    }
+
+    #[test]
+    fn receive_overrun() {
+        let (mut acia, line) = setup();
+        line.rx.lock().unwrap().extend(b"ab");
+
+        acia.tick(1);
+        acia.tick(FRAME);
+        assert_eq!(acia.read(REG_STATUS) & ST_OVERRUN, ST_OVERRUN);
+        assert_eq!(acia.read(REG_DATA), b'a');
+        assert_eq!(acia.read(REG_STATUS) & ST_OVERRUN, 0);
+    }
+
+    #[test]
+    fn receiver_disabled() {
+        let (mut acia, line) = setup();
+        acia.write(REG_COMMAND, 0);
+        line.rx.lock().unwrap().push_back(b'a');
+
+        acia.tick(FRAME);
+        assert_eq!(acia.read(REG_STATUS) & ST_RX_FULL, 0);
+        assert_eq!(line.rx.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn transmit_irq() {
+        let (mut acia, _) = setup();
+        acia.write(REG_COMMAND, CMD_DTR | CMD_RX_IRQ_DISABLE | TX_IRQ);
+        acia.write(REG_DATA, b'A');
+        acia.tick(1);
+        assert!(acia.irq());
+    }
+
+    #[test]
+    fn echo_mode() {
+        let (mut acia, line) = setup();
+        acia.write(REG_COMMAND, CMD_DTR | CMD_ECHO);
+        line.rx.lock().unwrap().push_back(b'x');
+
+        acia.tick(1);
+        assert_eq!(*line.tx.lock().unwrap(), b"x");
+        assert_eq!(acia.read(REG_DATA), b'x');
+    }
+
+    #[test]
+    fn programmed_reset() {
+        let (mut acia, _) = setup();
+        acia.write(REG_COMMAND, 0b1110_1011);
+        acia.write(REG_STATUS, 0);
+        assert_eq!(acia.read(REG_COMMAND), 0b1110_0010);
+        assert_eq!(acia.read(REG_CONTROL), 0b0001_1110);
+    }
+
+    #[test]
+    fn seven_bit_words() {
+        let (mut acia, line) = setup();
+        acia.write(REG_CONTROL, 0b0011_0000); // external clock, 7 bits
+        line.rx.lock().unwrap().push_back(0xC1);
+
+        acia.tick(1);
+        assert_eq!(acia.read(REG_DATA), 0x41);
+    }
+}
@@ -1,52 +1,446 @@
 use super::types::*;
-use crate::mem::Memory;
+use crate::mem::{Device, Dma, Memory};
 use std::cell::RefCell;
+use std::fmt;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
-type MemRange = (Word, Word);
+// Shared reference to a device plugged into the bus. By default, devices are shared through
+// `Rc<RefCell<_>>`. Using `Arc<Mutex<_>>` instead makes the whole bus `Send` and `Sync`,
+// so it can be moved to another thread together with the CPU.
+pub trait DeviceRef {
+    fn read(&self, addr: Word) -> Byte;
+    fn write(&self, addr: Word, data: Byte);
+    fn tick(&self, cycles: u64);
+    fn irq(&self) -> bool;
+    fn nmi(&self) -> bool;
+    fn rdy(&self) -> bool;
+    fn so(&self) -> bool;
+    fn take_dma(&self) -> Option<Dma>;
+    fn poke(&self, addr: Word, data: Byte);
+    fn same(&self, other: &Self) -> bool;
+}
+
+impl<M: Device + ?Sized> DeviceRef for Rc<RefCell<M>> {
+    fn read(&self, addr: Word) -> Byte {
+        self.borrow().read(addr)
+    }
+
+    fn write(&self, addr: Word, data: Byte) {
+        self.borrow_mut().write(addr, data)
+    }
+
+    fn tick(&self, cycles: u64) {
+        self.borrow_mut().tick(cycles)
+    }
+
+    fn irq(&self) -> bool {
+        self.borrow().irq()
+    }
+
+    fn nmi(&self) -> bool {
+        self.borrow().nmi()
+    }
+
+    fn rdy(&self) -> bool {
+        self.borrow().rdy()
+    }
+
+    fn so(&self) -> bool {
+        self.borrow().so()
+    }
+
+    fn take_dma(&self) -> Option<Dma> {
+        self.borrow_mut().take_dma()
+    }
+
+    fn poke(&self, addr: Word, data: Byte) {
+        self.borrow_mut().poke(addr, data)
+    }
+
+    fn same(&self, other: &Self) -> bool {
+        Rc::ptr_eq(self, other)
+    }
+}
+
+impl<M: Device + ?Sized> DeviceRef for Arc<Mutex<M>> {
+    fn read(&self, addr: Word) -> Byte {
+        self.lock().unwrap().read(addr)
+    }
+
+    fn write(&self, addr: Word, data: Byte) {
+        self.lock().unwrap().write(addr, data)
+    }
+
+    fn tick(&self, cycles: u64) {
+        self.lock().unwrap().tick(cycles)
+    }
+
+    fn irq(&self) -> bool {
+        self.lock().unwrap().irq()
+    }
+
+    fn nmi(&self) -> bool {
+        self.lock().unwrap().nmi()
+    }
+
+    fn rdy(&self) -> bool {
+        self.lock().unwrap().rdy()
+    }
 
-struct MappedMemory {
+    fn so(&self) -> bool {
+        self.lock().unwrap().so()
+    }
+
+    fn take_dma(&self) -> Option<Dma> {
+        self.lock().unwrap().take_dma()
+    }
+
+    fn poke(&self, addr: Word, data: Byte) {
+        self.lock().unwrap().poke(addr, data)
+    }
+
+    fn same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(self, other)
+    }
+}
+
+struct MappedMemory<D> {
     range: MemRange,
-    device: Rc<RefCell<dyn Memory>>,
+    device: D,
+    primary: bool, // the first mapping of the device, the others are just more windows into it
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+// A single transfer that went through the bus. The cycle is the number of bus ticks that
+// happened before the access, which is the CPU cycle the instruction was executed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: Word,
+    pub value: Byte,
+    pub kind: AccessKind,
+    pub cycle: u64,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Read => "read ",
+            AccessKind::Write => "write",
+        };
+        write!(
+            f,
+            "[{:>8}] {} 0x{:04X} = 0x{:02X}",
+            self.cycle, kind, self.addr, self.value
+        )
+    }
+}
+
+struct Transfer {
+    dma: Dma,
+    wait_cycles: u8,
+    done: u16,
+    latch: Option<Byte>,
 }
 
-pub struct Bus {
-    devices: Vec<MappedMemory>,
+// Watchpoint matches accesses to a range of addresses, optionally narrowed down
+// to a kind of access and to a specific value being transferred.
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    pub range: MemRange,
+    pub kind: Option<AccessKind>,
+    pub value: Option<Byte>,
 }
 
-impl Bus {
-    pub fn new() -> Bus {
+impl Watchpoint {
+    pub fn new(range: MemRange) -> Watchpoint {
+        Watchpoint {
+            range,
+            kind: None,
+            value: None,
+        }
+    }
+
+    pub fn on(mut self, kind: AccessKind) -> Watchpoint {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn with_value(mut self, value: Byte) -> Watchpoint {
+        self.value = Some(value);
+        self
+    }
+
+    pub fn matches(&self, access: &Access) -> bool {
+        self.range.0 <= access.addr
+            && access.addr <= self.range.1
+            && self.kind.is_none_or(|kind| kind == access.kind)
+            && self.value.is_none_or(|value| value == access.value)
+    }
+}
+
+type Hook = Box<dyn FnMut(&Access) + Send>;
+
+pub struct Bus<D = Rc<RefCell<dyn Device>>> {
+    devices: Vec<MappedMemory<D>>,
+    hooks: Mutex<Vec<Hook>>,
+    watchpoints: Vec<Watchpoint>,
+    tracing: bool, // any hooks or watchpoints, so the accesses have to be traced
+    watch_hit: Mutex<Option<Access>>,
+    transfer: Option<Transfer>,
+    cycle: u64,
+}
+
+// Bus that can be shared between threads. All devices plugged into it must be `Send`.
+pub type SyncBus = Bus<Arc<Mutex<dyn Device + Send>>>;
+
+impl<D: DeviceRef> Bus<D> {
+    pub fn new() -> Bus<D> {
         Bus {
             devices: Vec::new(),
+            hooks: Mutex::new(Vec::new()),
+            watchpoints: Vec::new(),
+            tracing: false,
+            watch_hit: Mutex::new(None),
+            transfer: None,
+            cycle: 0,
         }
     }
 
-    pub fn plug_in(
-        &mut self,
-        range: MemRange,
-        device: Rc<RefCell<dyn Memory>>,
-    ) -> Result<(), String> {
-        self.devices.push(MappedMemory { range, device });
+    // Maps the device into the given range. Ranges may be nested, in which case the device that was
+    // plugged in first takes precedence (e.g. an I/O device on top of a RAM chip that covers the whole
+    // address space), but they may not partially overlap.
+    pub fn plug_in(&mut self, range: MemRange, device: D) -> Result<(), String> {
+        if range.0 > range.1 {
+            return Err(format!("invalid range 0x{:04X}-0x{:04X}", range.0, range.1));
+        }
+
+        for mapped in &self.devices {
+            let other = mapped.range;
+            let disjoint = range.1 < other.0 || other.1 < range.0;
+            let nested = (other.0 <= range.0 && range.1 <= other.1)
+                || (range.0 <= other.0 && other.1 <= range.1);
+            if !disjoint && !nested {
+                return Err(format!(
+                    "range 0x{:04X}-0x{:04X} partially overlaps 0x{:04X}-0x{:04X}",
+                    range.0, range.1, other.0, other.1
+                ));
+            }
+        }
+
+        let primary = !self
+            .devices
+            .iter()
+            .any(|mapped| mapped.device.same(&device));
+        self.devices.push(MappedMemory {
+            range,
+            device,
+            primary,
+        });
         Ok(())
     }
+
+    // Copies the data into memory starting at the given address, bypassing write protection.
+    // It fails if a part of the data does not land on any device or does not fit into the address space.
+    pub fn load(&mut self, addr: Word, data: &[Byte]) -> Result<(), String> {
+        if addr as usize + data.len() > 0x10000 {
+            return Err(format!(
+                "{} bytes at 0x{:04X} do not fit into the address space",
+                data.len(),
+                addr
+            ));
+        }
+
+        for (i, &byte) in data.iter().enumerate() {
+            let addr = addr + i as Word;
+            let mapped = self
+                .devices
+                .iter()
+                .find(|mapped| mapped.range.0 <= addr && addr <= mapped.range.1)
+                .ok_or_else(|| format!("nothing is mapped at 0x{:04X}", addr))?;
+            mapped.device.poke(addr - mapped.range.0, byte);
+        }
+        Ok(())
+    }
+
+    // Registers a function that is called on every read and write going through the bus.
+    // Hooks must not access the bus themselves.
+    pub fn add_hook(&mut self, hook: impl FnMut(&Access) + Send + 'static) {
+        self.hooks.get_mut().unwrap().push(Box::new(hook));
+        self.tracing = true;
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+        self.tracing = true;
+    }
+
+    // Returns the first access that triggered a watchpoint since the last call, if any.
+    // The caller is expected to check it after each CPU tick and stop the execution.
+    pub fn take_watch_hit(&mut self) -> Option<Access> {
+        self.watch_hit.get_mut().unwrap().take()
+    }
+
+    // Devices plugged into several ranges are only counted once when ticking them
+    // and collecting the signals they drive.
+    fn unique_devices(&self) -> impl Iterator<Item = &MappedMemory<D>> {
+        self.devices.iter().filter(|mapped| mapped.primary)
+    }
+
+    // Advances the bus clock and all plugged devices by the given number of cycles.
+    // It is supposed to be called in lockstep with the CPU.
+    pub fn tick(&mut self, cycles: u64) {
+        for mapped_device in self.unique_devices() {
+            mapped_device.device.tick(cycles);
+        }
+
+        for _ in 0..cycles {
+            if self.transfer.is_none() {
+                self.start_dma();
+            }
+            if self.transfer.is_some() {
+                self.step_dma();
+            }
+            self.cycle += 1;
+        }
+    }
+
+    fn start_dma(&mut self) {
+        let dma = self
+            .unique_devices()
+            .find_map(|mapped| mapped.device.take_dma());
+
+        if let Some(dma) = dma {
+            self.transfer = Some(Transfer {
+                dma,
+                wait_cycles: 1 + (self.cycle % 2) as u8,
+                done: 0,
+                latch: None,
+            });
+        }
+    }
+
+    fn step_dma(&mut self) {
+        let mut transfer = self.transfer.take().unwrap();
+
+        if transfer.wait_cycles > 0 {
+            transfer.wait_cycles -= 1;
+        } else if let Some(data) = transfer.latch.take() {
+            let mut dst = transfer.dma.dst;
+            if transfer.dma.increment_dst {
+                dst = dst.wrapping_add(transfer.done);
+            }
+            self.write(dst, data);
+            transfer.done += 1;
+        } else {
+            let mut src = transfer.dma.src;
+            if transfer.dma.increment_src {
+                src = src.wrapping_add(transfer.done);
+            }
+            transfer.latch = Some(self.read(src));
+        }
+
+        if transfer.done < transfer.dma.len {
+            self.transfer = Some(transfer);
+        }
+    }
+
+    // SO is asserted while any device pulls it. It is usually driven by a disk controller.
+    pub fn so(&self) -> bool {
+        self.unique_devices().any(|mapped| mapped.device.so())
+    }
+
+    // RDY is low while a DMA transfer is in progress or while any device pulls it down.
+    pub fn rdy(&self) -> bool {
+        self.transfer.is_none() && self.unique_devices().all(|mapped| mapped.device.rdy())
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    // IRQ is a wired-OR line shared by all devices: it is asserted while at least one of them pulls it.
+    pub fn irq(&self) -> bool {
+        self.unique_devices().any(|mapped| mapped.device.irq())
+    }
+
+    // NMI is routed separately from IRQ, but is shared the same way.
+    pub fn nmi(&self) -> bool {
+        self.unique_devices().any(|mapped| mapped.device.nmi())
+    }
+
+    // Returns the ranges of the devices that currently assert IRQ. Useful for debugging.
+    pub fn irq_sources(&self) -> Vec<MemRange> {
+        self.unique_devices()
+            .filter(|mapped| mapped.device.irq())
+            .map(|mapped| mapped.range)
+            .collect()
+    }
+
+    // Returns the ranges of the devices that currently assert NMI.
+    pub fn nmi_sources(&self) -> Vec<MemRange> {
+        self.unique_devices()
+            .filter(|mapped| mapped.device.nmi())
+            .map(|mapped| mapped.range)
+            .collect()
+    }
+
+    // Checked without taking any locks, since it is called on every access.
+    fn trace(&self, addr: Word, value: Byte, kind: AccessKind) {
+        if !self.tracing {
+            return;
+        }
+
+        let access = Access {
+            addr,
+            value,
+            kind,
+            cycle: self.cycle,
+        };
+
+        for hook in self.hooks.lock().unwrap().iter_mut() {
+            hook(&access);
+        }
+
+        let hit = self.watchpoints.iter().any(|wp| wp.matches(&access));
+        let mut watch_hit = self.watch_hit.lock().unwrap();
+        if hit && watch_hit.is_none() {
+            *watch_hit = Some(access);
+        }
+    }
 }
 
-impl Memory for Bus {
+impl<D: DeviceRef> Default for Bus<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: DeviceRef> Memory for Bus<D> {
     fn read(&self, addr: Word) -> Byte {
+        let mut data = 0;
         for mapped_device in &self.devices {
             if mapped_device.range.0 <= addr && addr <= mapped_device.range.1 {
-                let device = mapped_device.device.borrow();
-                return device.read(addr - mapped_device.range.0);
+                data = mapped_device.device.read(addr - mapped_device.range.0);
+                break;
             }
         }
-        0
+        self.trace(addr, data, AccessKind::Read);
+        data
     }
 
     fn write(&mut self, addr: Word, data: Byte) {
-        for mapped_device in &mut self.devices {
+        self.trace(addr, data, AccessKind::Write);
+        for mapped_device in &self.devices {
             if mapped_device.range.0 <= addr && addr <= mapped_device.range.1 {
-                let mut device = mapped_device.device.borrow_mut();
-                device.write(addr - mapped_device.range.0, data);
+                mapped_device
+                    .device
+                    .write(addr - mapped_device.range.0, data);
                 return;
             }
         }
@@ -56,6 +450,7 @@ impl Memory for Bus {
 #[cfg(test)]
 mod bus_test {
     use super::*;
+    use crate::mem::{BankedMemory, Ram};
     use std::rc::Rc;
 
     struct TestDevice {
@@ -79,6 +474,105 @@ mod bus_test {
         }
     }
 
+    impl Device for TestDevice {}
+
+    struct Counter {
+        cycles: u64,
+    }
+
+    impl Memory for Counter {
+        fn read(&self, _addr: Word) -> Byte {
+            self.cycles as Byte
+        }
+
+        fn write(&mut self, _addr: Word, _data: Byte) {}
+    }
+
+    struct Interrupter {
+        irq: bool,
+        nmi: bool,
+    }
+
+    impl Memory for Interrupter {
+        fn read(&self, _addr: Word) -> Byte {
+            0
+        }
+
+        fn write(&mut self, _addr: Word, data: Byte) {
+            self.irq = data & 1 != 0;
+            self.nmi = data & 2 != 0;
+        }
+    }
+
+    impl Device for Interrupter {
+        fn irq(&self) -> bool {
+            self.irq
+        }
+
+        fn nmi(&self) -> bool {
+            self.nmi
+        }
+    }
+
+    // Sprite DMA controller: writing a page number into the register
+    // copies the page into the sprite memory through the data port.
+    struct SpriteDma {
+        request: Option<Dma>,
+        sprites: Vec<Byte>,
+    }
+
+    impl Memory for SpriteDma {
+        fn read(&self, _addr: Word) -> Byte {
+            0
+        }
+
+        fn write(&mut self, addr: Word, data: Byte) {
+            match addr {
+                0 => self.sprites.push(data),
+                1 => self.request = Some(Dma::to_port((data as Word) << 8, 0x2004, 256)),
+                _ => {}
+            }
+        }
+    }
+
+    impl Device for SpriteDma {
+        fn take_dma(&mut self) -> Option<Dma> {
+            self.request.take()
+        }
+    }
+
+    impl Device for Counter {
+        fn tick(&mut self, cycles: u64) {
+            self.cycles += cycles;
+        }
+    }
+
+    // Asks the bus for a single DMA transfer.
+    struct DmaRequest(Option<Dma>);
+
+    impl Memory for DmaRequest {
+        fn read(&self, _addr: Word) -> Byte {
+            0
+        }
+
+        fn write(&mut self, _addr: Word, _data: Byte) {}
+    }
+
+    impl Device for DmaRequest {
+        fn take_dma(&mut self) -> Option<Dma> {
+            self.0.take()
+        }
+    }
+
+    fn run_dma(bus: &mut Bus, dma: Dma) {
+        let request = Rc::new(RefCell::new(DmaRequest(Some(dma))));
+        bus.plug_in((0xFF00, 0xFF00), request).unwrap();
+        bus.tick(1);
+        while !bus.rdy() {
+            bus.tick(1);
+        }
+    }
+
     #[test]
     fn read() {
         let mut bus = Bus::new();
@@ -136,4 +630,290 @@ mod bus_test {
         assert_eq!(device.addr, 0x0000);
         assert_eq!(device.data, 0xFF);
     }
+
+    #[test]
+    fn hooks() {
+        let mut bus = Bus::new();
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        bus.plug_in((0x0000, 0xFFFF), ram).unwrap();
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let hook_log = log.clone();
+        bus.add_hook(move |access| hook_log.lock().unwrap().push(*access));
+
+        bus.write(0x0200, 0xAB);
+        bus.tick(1);
+        bus.read(0x0200);
+
+        let log = log.lock().unwrap();
+        assert_eq!(
+            *log,
+            vec![
+                Access {
+                    addr: 0x0200,
+                    value: 0xAB,
+                    kind: AccessKind::Write,
+                    cycle: 0,
+                },
+                Access {
+                    addr: 0x0200,
+                    value: 0xAB,
+                    kind: AccessKind::Read,
+                    cycle: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut bus = Bus::new();
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        bus.plug_in((0x0000, 0xFFFF), ram).unwrap();
+
+        let wp = Watchpoint::new((0x0010, 0x001F))
+            .on(AccessKind::Write)
+            .with_value(0x42);
+        bus.add_watchpoint(wp);
+
+        bus.write(0x0020, 0x42); // out of range
+        bus.write(0x0010, 0x41); // wrong value
+        bus.read(0x0010); // wrong kind
+        assert_eq!(bus.take_watch_hit(), None);
+
+        bus.write(0x0015, 0x42);
+        bus.write(0x0016, 0x42);
+        let hit = bus.take_watch_hit().unwrap();
+        assert_eq!(hit.addr, 0x0015);
+        assert_eq!(bus.take_watch_hit(), None);
+    }
+
+    #[test]
+    fn sync_bus() {
+        let ram = Arc::new(Mutex::new(Ram::new()));
+
+        let mut bus = SyncBus::new();
+        bus.plug_in((0x0000, 0xFFFF), ram.clone()).unwrap();
+
+        let handle = std::thread::spawn(move || {
+            bus.write(0x1234, 0xAB);
+            bus
+        });
+
+        let bus = handle.join().unwrap();
+        assert_eq!(bus.read(0x1234), 0xAB);
+        assert_eq!(ram.lock().unwrap().read(0x1234), 0xAB);
+    }
+
+    #[test]
+    fn tick() {
+        let counter = Rc::new(RefCell::new(Counter { cycles: 0 }));
+
+        let mut bus = Bus::new();
+        bus.plug_in((0x4000, 0x4000), counter.clone()).unwrap();
+        bus.tick(1);
+        bus.tick(2);
+
+        assert_eq!(bus.cycle(), 3);
+        assert_eq!(counter.borrow().cycles, 3);
+        assert_eq!(bus.read(0x4000), 3);
+    }
+
+    #[test]
+    fn tick_mirrored_device() {
+        let counter = Rc::new(RefCell::new(Counter { cycles: 0 }));
+
+        let mut bus = Bus::new();
+        bus.plug_in((0x4000, 0x4000), counter.clone()).unwrap();
+        bus.plug_in((0x5000, 0x5000), counter.clone()).unwrap();
+        bus.tick(5);
+
+        assert_eq!(counter.borrow().cycles, 5);
+        assert_eq!(bus.read(0x5000), 5);
+    }
+
+    #[test]
+    fn interrupts_mirrored_device() {
+        let device = Rc::new(RefCell::new(Interrupter {
+            irq: false,
+            nmi: false,
+        }));
+
+        let mut bus = Bus::new();
+        bus.plug_in((0x4000, 0x4000), device.clone()).unwrap();
+        bus.plug_in((0x5000, 0x5000), device).unwrap();
+        bus.write(0x5000, 3);
+
+        assert_eq!(bus.irq_sources(), vec![(0x4000, 0x4000)]);
+        assert_eq!(bus.nmi_sources(), vec![(0x4000, 0x4000)]);
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut bus = Bus::new();
+        for addr in [0x4000, 0x4001] {
+            let device = Rc::new(RefCell::new(Interrupter {
+                irq: false,
+                nmi: false,
+            }));
+            bus.plug_in((addr, addr), device).unwrap();
+        }
+
+        assert!(!bus.irq());
+        assert!(!bus.nmi());
+
+        bus.write(0x4001, 1);
+        assert!(bus.irq());
+        assert!(!bus.nmi());
+        assert_eq!(bus.irq_sources(), vec![(0x4001, 0x4001)]);
+
+        bus.write(0x4000, 3);
+        assert!(bus.nmi());
+        assert_eq!(bus.irq_sources(), vec![(0x4000, 0x4000), (0x4001, 0x4001)]);
+        assert_eq!(bus.nmi_sources(), vec![(0x4000, 0x4000)]);
+    }
+
+    fn sprite_dma_cycles(start_cycle: u64) -> u64 {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        let dma = Rc::new(RefCell::new(SpriteDma {
+            request: None,
+            sprites: Vec::new(),
+        }));
+
+        let mut bus: Bus = Bus::new();
+        bus.plug_in((0x2004, 0x2005), dma.clone()).unwrap();
+        bus.plug_in((0x0000, 0xFFFF), ram).unwrap();
+
+        for i in 0..=255 {
+            bus.write(0x0300 + i, i as Byte);
+        }
+
+        bus.tick(start_cycle);
+        bus.write(0x2005, 0x03);
+        assert!(bus.rdy());
+
+        let mut cycles = 0;
+        loop {
+            bus.tick(1);
+            cycles += 1;
+            if bus.rdy() {
+                break;
+            }
+        }
+
+        let sprites = &dma.borrow().sprites;
+        assert_eq!(sprites.len(), 256);
+        assert!(sprites.iter().enumerate().all(|(i, &b)| b == i as Byte));
+        cycles
+    }
+
+    #[test]
+    fn dma() {
+        assert_eq!(sprite_dma_cycles(0), 513);
+        assert_eq!(sprite_dma_cycles(1), 514);
+    }
+
+    #[test]
+    fn dma_copy() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        let mut bus: Bus = Bus::new();
+        bus.plug_in((0x0000, 0xFFFF), ram).unwrap();
+        bus.write(0x1000, 0xAA);
+        bus.write(0x1001, 0xBB);
+
+        run_dma(&mut bus, Dma::copy(0x1000, 0x2000, 2));
+
+        assert_eq!(bus.read(0x2000), 0xAA);
+        assert_eq!(bus.read(0x2001), 0xBB);
+    }
+
+    #[test]
+    fn dma_from_port() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        let port = Rc::new(RefCell::new(Ram::with_size(1).unwrap()));
+        port.borrow_mut().write(0x0000, 0xCC);
+
+        let mut bus: Bus = Bus::new();
+        bus.plug_in((0x4000, 0x4000), port).unwrap();
+        bus.plug_in((0x0000, 0xFFFF), ram).unwrap();
+
+        run_dma(&mut bus, Dma::from_port(0x4000, 0x2000, 3));
+
+        assert_eq!(bus.read(0x2000), 0xCC);
+        assert_eq!(bus.read(0x2002), 0xCC);
+        assert_eq!(bus.read(0x2003), 0x00);
+    }
+
+    #[test]
+    fn overlap() {
+        let ram = || Rc::new(RefCell::new(Ram::new()));
+
+        let mut bus = Bus::new();
+        bus.plug_in((0x0200, 0x02FF), ram()).unwrap();
+        bus.plug_in((0x0000, 0xFFFF), ram()).unwrap();
+        bus.plug_in((0x0280, 0x028F), ram()).unwrap();
+
+        assert!(bus.plug_in((0x0100, 0x0200), ram()).is_err());
+        assert!(bus.plug_in((0x02FF, 0x0300), ram()).is_err());
+        assert!(bus.plug_in((0x0300, 0x0200), ram()).is_err());
+    }
+
+    #[test]
+    fn load() {
+        let ram = Rc::new(RefCell::new(Ram::new()));
+        ram.borrow_mut().write_protect((0x8000, 0xFFFF));
+
+        let mut bus = Bus::new();
+        bus.plug_in((0x0000, 0xFFFF), ram).unwrap();
+        bus.load(0xFFFE, &[0x12, 0x34]).unwrap();
+
+        assert_eq!(bus.read(0xFFFE), 0x12);
+        assert_eq!(bus.read(0xFFFF), 0x34);
+        assert!(bus.load(0xFFFF, &[0x12, 0x34]).is_err());
+    }
+
+    #[test]
+    fn load_unmapped() {
+        let ram = Rc::new(RefCell::new(Ram::with_size(0x0100).unwrap()));
+
+        let mut bus = Bus::new();
+        bus.plug_in((0x0000, 0x00FF), ram).unwrap();
+
+        assert!(bus.load(0x00FF, &[0x01]).is_ok());
+        assert!(bus.load(0x00FF, &[0x01, 0x02]).is_err());
+    }
+
+    #[test]
+    fn sized_ram() {
+        let ram = Rc::new(RefCell::new(Ram::with_size(0x0800).unwrap()));
+
+        let mut bus = Bus::new();
+        bus.plug_in((0x8000, 0x87FF), ram.clone()).unwrap();
+        bus.write(0x87FF, 0xAB);
+
+        assert_eq!(bus.read(0x87FF), 0xAB);
+        assert_eq!(ram.borrow().read(0x07FF), 0xAB);
+    }
+
+    #[test]
+    fn banked_memory() {
+        let banks = (0..8)
+            .map(|_| Box::new(Ram::with_size(0x4000).unwrap()) as Box<dyn Memory + Send>)
+            .collect();
+        let banked = Rc::new(RefCell::new(
+            BankedMemory::new(banks, 0x4000, 0x4000).unwrap(),
+        ));
+
+        let mut bus = Bus::new();
+        bus.plug_in((0x8000, 0xC000), banked.clone()).unwrap();
+
+        for bank in 0..8 {
+            bus.write(0xC000, bank);
+            bus.write(0x8000, bank + 0x10);
+        }
+        for bank in 0..8 {
+            bus.write(0xC000, bank);
+            assert_eq!(bus.read(0x8000), bank + 0x10);
+        }
+    }
 }
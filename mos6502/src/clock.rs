@@ -1,25 +1,39 @@
 use std::time::{Duration, Instant};
 
-const MHZ: f32 = 1_000_000.0;
+const MHZ: f64 = 1_000_000.0;
 
 // Oscillator generates a clock signal at a given frequency.
 // It generally prevents the CPU from running "too fast" on modern hardware.
+// Every tick is scheduled relative to the start, so the time spent between
+// the ticks does not add up and the average frequency stays accurate.
 pub struct Oscillator {
-    freq: Duration,
+    freq: f64, // Hz
     start: Instant,
+    ticks: u64,
 }
 
 impl Oscillator {
-    pub fn with_frequency(freq: f32) -> Oscillator {
-        Oscillator {
-            freq: Duration::from_nanos((MHZ / freq) as u64),
-            start: Instant::now(),
+    // The frequency is given in MHz.
+    pub fn with_frequency(freq: f32) -> Result<Oscillator, String> {
+        if !freq.is_finite() || freq <= 0.0 {
+            return Err(format!("frequency must be positive, got {}", freq));
         }
+        Ok(Oscillator {
+            freq: freq as f64 * MHZ,
+            start: Instant::now(),
+            ticks: 0,
+        })
     }
 
     pub fn tick(&mut self) {
-        while self.start.elapsed() < self.freq {}
-        self.start = Instant::now();
+        self.ticks += 1;
+        let deadline = self.start + self.offset(self.ticks);
+        while Instant::now() < deadline {}
+    }
+
+    // The time from the start at which the given tick is due.
+    fn offset(&self, ticks: u64) -> Duration {
+        Duration::from_secs_f64(ticks as f64 / self.freq)
     }
 }
 
@@ -31,3 +45,37 @@ impl Iterator for Oscillator {
         Some(())
     }
 }
+
+#[cfg(test)]
+mod clock_test {
+    use super::*;
+
+    #[test]
+    fn frequency() {
+        let start = Instant::now();
+        let mut clock = Oscillator::with_frequency(1.0).unwrap();
+        for _ in 0..10_000 {
+            clock.tick();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn catches_up() {
+        // a late tick does not move the later deadlines
+        let mut clock = Oscillator::with_frequency(0.001).unwrap();
+        let start = clock.start;
+        std::thread::sleep(Duration::from_millis(2));
+        clock.tick();
+        assert_eq!(clock.start, start);
+        assert_eq!(clock.offset(1), Duration::from_millis(1));
+        assert_eq!(clock.offset(10), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn invalid_frequency() {
+        for freq in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(Oscillator::with_frequency(freq).is_err());
+        }
+    }
+}
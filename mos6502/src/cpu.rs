@@ -1,11 +1,11 @@
 use crate::mem::Memory;
 use crate::opcodes::*;
 use crate::types::*;
+use std::cell::Cell;
 
 const FL_CARRY: Flag = 1 << 0;
 const FL_ZERO: Flag = 1 << 1;
 const FL_NO_INTERRUPT: Flag = 1 << 2;
-const FL_DECIMAL: Flag = 1 << 3;
 const FL_BREAK: Flag = 1 << 4;
 const FL_UNUSED: Flag = 1 << 5;
 const FL_OVERFLOW: Flag = 1 << 6;
@@ -39,6 +39,34 @@ struct Operand {
     page_cross: bool,
 }
 
+// Counts the write cycles at the end of an instruction. When RDY is pulled low, the 6502 only stops
+// on a read cycle, so the writes that finish an instruction are still completed.
+struct WriteTracker<'a> {
+    mem: &'a mut dyn Memory,
+    trailing_writes: Cell<u8>,
+}
+
+impl<'a> WriteTracker<'a> {
+    fn new(mem: &'a mut dyn Memory) -> Self {
+        Self {
+            mem,
+            trailing_writes: Cell::new(0),
+        }
+    }
+}
+
+impl Memory for WriteTracker<'_> {
+    fn read(&self, addr: Word) -> Byte {
+        self.trailing_writes.set(0);
+        self.mem.read(addr)
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        self.trailing_writes.set(self.trailing_writes.get() + 1);
+        self.mem.write(addr, data);
+    }
+}
+
 pub struct CPU {
     sp: Byte, // Stack pointer
     pc: Word, // Program counter
@@ -49,6 +77,23 @@ pub struct CPU {
     y: Byte, // Y register
 
     cycles: u8, // Cycles remaining
+
+    irq: bool,         // IRQ line is asserted
+    nmi: bool,         // NMI line is asserted
+    nmi_pending: bool, // NMI edge has been detected, but not handled yet
+
+    rdy: bool,           // RDY line is high, so the CPU may run
+    trailing_writes: u8, // Write cycles at the end of the current instruction
+
+    so: bool, // SO line is asserted
+
+    last_opcode: Option<Byte>, // Opcode executed by the last real tick, None for interrupts
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
@@ -61,6 +106,13 @@ impl CPU {
             x: 0,
             y: 0,
             cycles: 0,
+            irq: false,
+            nmi: false,
+            nmi_pending: false,
+            rdy: true,
+            trailing_writes: 0,
+            so: false,
+            last_opcode: None,
         }
     }
 
@@ -258,7 +310,7 @@ impl CPU {
 
             _ => {
                 println!("---- last cpu state ----");
-                print_state(&self);
+                print_state(self);
                 panic!("invalid opcode: 0x{:02x}", opcode);
             }
         }
@@ -278,73 +330,57 @@ impl CPU {
     fn stack_pop(&mut self, mem: &mut dyn Memory) -> Byte {
         self.sp = self.sp.wrapping_add(1);
         let addr = 0x0100 | (self.sp as Word);
-        let data = mem.read(addr);
-        data
+        mem.read(addr)
     }
 
+    // Reads the operand of the instruction. Instructions that only store to memory use `address`
+    // instead, so they do not touch the target before writing it, which matters for I/O registers.
     fn fetch(&mut self, mem: &mut dyn Memory, mode: AddrMode) -> Operand {
+        if let AddrMode::Acc = mode {
+            return Operand {
+                addr: 0x00,
+                value: self.a,
+                page_cross: false,
+            };
+        }
+
+        let (addr, page_cross) = self.address(mem, mode);
+        let value = mem.read(addr);
+        Operand {
+            addr,
+            value,
+            page_cross,
+        }
+    }
+
+    // Decodes the effective address of the operand and tells if indexing has crossed a page.
+    fn address(&mut self, mem: &mut dyn Memory, mode: AddrMode) -> (Word, bool) {
         match mode {
             AddrMode::Imm => {
                 let addr = self.pc;
-                let value = mem.read(addr);
                 self.pc += 1;
-
-                Operand {
-                    addr,
-                    value,
-                    page_cross: false,
-                }
+                (addr, false)
             }
             AddrMode::Zp => {
                 let addr = mem.read(self.pc) as Word;
                 self.pc += 1;
-
-                let value = mem.read(addr);
-                Operand {
-                    addr,
-                    value,
-                    page_cross: false,
-                }
+                (addr, false)
             }
             AddrMode::ZpX => {
                 let zp_addr = mem.read(self.pc);
                 self.pc += 1;
-
-                let addr = zp_addr.wrapping_add(self.x) as Word;
-                let value = mem.read(addr as Word);
-
-                Operand {
-                    addr,
-                    value,
-                    page_cross: false,
-                }
+                (zp_addr.wrapping_add(self.x) as Word, false)
             }
             AddrMode::ZpY => {
                 let zp_addr = mem.read(self.pc);
                 self.pc += 1;
-
-                let addr = zp_addr.wrapping_add(self.y) as Word;
-                let value = mem.read(addr);
-
-                Operand {
-                    addr,
-                    value,
-                    page_cross: false,
-                }
+                (zp_addr.wrapping_add(self.y) as Word, false)
             }
             AddrMode::Abs => {
                 let lo = mem.read(self.pc) as Word;
                 let hi = mem.read(self.pc + 1) as Word;
                 self.pc += 2;
-
-                let addr = (hi << 8) | lo;
-                let value = mem.read(addr);
-
-                Operand {
-                    addr,
-                    value,
-                    page_cross: false,
-                }
+                ((hi << 8) | lo, false)
             }
             AddrMode::AbsX => {
                 let lo = mem.read(self.pc) as Word;
@@ -353,14 +389,7 @@ impl CPU {
 
                 let addr = (hi << 8) | lo;
                 let addr_x = addr.wrapping_add(self.x as Word);
-                let page_cross = addr & 0xFF00 != addr_x & 0xFF00;
-                let value = mem.read(addr_x);
-
-                Operand {
-                    value,
-                    page_cross,
-                    addr: addr_x,
-                }
+                (addr_x, addr & 0xFF00 != addr_x & 0xFF00)
             }
             AddrMode::AbsY => {
                 let lo = mem.read(self.pc) as Word;
@@ -369,14 +398,7 @@ impl CPU {
 
                 let addr = (hi << 8) | lo;
                 let addr_y = addr.wrapping_add(self.y as Word);
-                let page_cross = addr & 0xFF00 != addr_y & 0xFF00;
-                let value = mem.read(addr_y);
-
-                Operand {
-                    value,
-                    page_cross,
-                    addr: addr_y,
-                }
+                (addr_y, addr & 0xFF00 != addr_y & 0xFF00)
             }
             AddrMode::Ind => {
                 let ptr_addr = {
@@ -386,26 +408,17 @@ impl CPU {
                     (hi << 8) | lo
                 };
 
-                let addr = {
-                    let lo = mem.read(ptr_addr) as Word;
-                    let mut hi = mem.read(ptr_addr + 1) as Word;
-
-                    // An original 6502 has does not correctly fetch the target address if the indirect vector falls on
-                    // a page boundary (e.g. $xxFF where xx is any value from $00 to $FF). In this case fetches the LSB
-                    // from $xxFF as expected but takes the MSB from $xx00.
-                    if ptr_addr & 0x00FF == 0x00FF {
-                        hi = mem.read(ptr_addr & 0xFF00) as Word;
-                    }
+                let lo = mem.read(ptr_addr) as Word;
+                let mut hi = mem.read(ptr_addr + 1) as Word;
 
-                    (hi << 8) | lo
-                };
-
-                let value = mem.read(addr);
-                Operand {
-                    addr,
-                    value,
-                    page_cross: false,
+                // An original 6502 has does not correctly fetch the target address if the indirect vector falls on
+                // a page boundary (e.g. $xxFF where xx is any value from $00 to $FF). In this case fetches the LSB
+                // from $xxFF as expected but takes the MSB from $xx00.
+                if ptr_addr & 0x00FF == 0x00FF {
+                    hi = mem.read(ptr_addr & 0xFF00) as Word;
                 }
+
+                ((hi << 8) | lo, false)
             }
             AddrMode::IndX => {
                 let ptr_addr = {
@@ -415,18 +428,9 @@ impl CPU {
                     addr as Word
                 };
 
-                let addr = {
-                    let lo = mem.read(ptr_addr) as Word;
-                    let hi = mem.read(ptr_addr + 1) as Word;
-                    (hi << 8) | lo
-                };
-
-                let value = mem.read(addr);
-                Operand {
-                    addr,
-                    value,
-                    page_cross: false,
-                }
+                let lo = mem.read(ptr_addr) as Word;
+                let hi = mem.read(ptr_addr + 1) as Word;
+                ((hi << 8) | lo, false)
             }
             AddrMode::IndY => {
                 let ptr_addr = mem.read(self.pc) as Word;
@@ -439,38 +443,20 @@ impl CPU {
                 };
 
                 let addr_y = addr.wrapping_add(self.y as Word);
-                let page_cross = addr & 0xFF00 != addr_y & 0xFF00;
-                let value = mem.read(addr_y);
-
-                Operand {
-                    addr: addr_y,
-                    value,
-                    page_cross,
-                }
+                (addr_y, addr & 0xFF00 != addr_y & 0xFF00)
             }
             AddrMode::Rel => {
                 let mut offset = mem.read(self.pc) as Word;
                 self.pc += 1;
 
                 if offset & (1 << 7) != 0 {
-                    offset = offset | 0xFF00;
+                    offset |= 0xFF00;
                 }
 
                 let addr = self.pc.wrapping_add(offset);
-                let page_cross = self.pc & 0xFF00 != addr & 0xFF00;
-                let value = mem.read(addr);
-
-                Operand {
-                    addr,
-                    value,
-                    page_cross,
-                }
+                (addr, self.pc & 0xFF00 != addr & 0xFF00)
             }
-            AddrMode::Acc => Operand {
-                addr: 0x00,
-                value: self.a,
-                page_cross: false,
-            },
+            AddrMode::Acc => unreachable!("accumulator has no address"),
         }
     }
 
@@ -578,20 +564,20 @@ impl CPU {
     }
 
     fn sta(&mut self, mem: &mut dyn Memory, mode: AddrMode, cycles: u8) -> u8 {
-        let f = self.fetch(mem, mode);
-        mem.write(f.addr, self.a);
+        let (addr, _) = self.address(mem, mode);
+        mem.write(addr, self.a);
         cycles
     }
 
     fn stx(&mut self, mem: &mut dyn Memory, mode: AddrMode, cycles: u8) -> u8 {
-        let f = self.fetch(mem, mode);
-        mem.write(f.addr, self.x);
+        let (addr, _) = self.address(mem, mode);
+        mem.write(addr, self.x);
         cycles
     }
 
     fn sty(&mut self, mem: &mut dyn Memory, mode: AddrMode, cycles: u8) -> u8 {
-        let f = self.fetch(mem, mode);
-        mem.write(f.addr, self.y);
+        let (addr, _) = self.address(mem, mode);
+        mem.write(addr, self.y);
         cycles
     }
 
@@ -845,21 +831,21 @@ impl CPU {
 
     fn and(&mut self, mem: &mut dyn Memory, mode: AddrMode, cycles: u8) -> u8 {
         let f = self.fetch(mem, mode);
-        self.a = self.a & f.value;
+        self.a &= f.value;
         self.set_zn(self.a);
         cycles
     }
 
     fn eor(&mut self, mem: &mut dyn Memory, mode: AddrMode, cycles: u8) -> u8 {
         let f = self.fetch(mem, mode);
-        self.a = self.a ^ f.value;
+        self.a ^= f.value;
         self.set_zn(self.a);
         cycles
     }
 
     fn ora(&mut self, mem: &mut dyn Memory, mode: AddrMode, cycles: u8) -> u8 {
         let f = self.fetch(mem, mode);
-        self.a = self.a | f.value;
+        self.a |= f.value;
         self.set_zn(self.a);
         cycles
     }
@@ -957,6 +943,63 @@ impl CPU {
         cycles
     }
 
+    // Hardware interrupts push the return address and the status the same way BRK does,
+    // but with the break flag cleared, so the handler can tell them apart.
+    fn interrupt(&mut self, mem: &mut dyn Memory, vector: Word) -> u8 {
+        self.stack_push(mem, self.pc as Byte);
+        self.stack_push(mem, (self.pc >> 8) as Byte);
+        self.stack_push(mem, self.p & !FL_BREAK);
+
+        self.set_flag(FL_NO_INTERRUPT, true);
+        self.pc = self.read_word(mem, vector);
+        7
+    }
+
+    // IRQ is level-triggered: the interrupt is taken before the next instruction
+    // for as long as the line is asserted and interrupts are not disabled.
+    pub fn set_irq(&mut self, level: bool) {
+        self.irq = level;
+    }
+
+    // NMI is edge-triggered: only the transition to the asserted state causes an interrupt,
+    // and it cannot be masked.
+    pub fn set_nmi(&mut self, level: bool) {
+        if level && !self.nmi {
+            self.nmi_pending = true;
+        }
+        self.nmi = level;
+    }
+
+    // Pulling RDY low halts the CPU on the next read cycle, so a DMA controller or a video chip
+    // can take over the bus. The CPU resumes where it stopped once RDY goes high again.
+    pub fn set_rdy(&mut self, level: bool) {
+        self.rdy = level;
+    }
+
+    // SO (set overflow) sets the V flag on a falling edge of the pin, which is when the line
+    // becomes asserted. Disk drives use it to signal that a byte is ready, while the firmware
+    // waits for it in a tight BVC loop.
+    pub fn set_so(&mut self, level: bool) {
+        if level && !self.so {
+            self.set_flag(FL_OVERFLOW, true);
+        }
+        self.so = level;
+    }
+
+    pub fn pc(&self) -> Word {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, addr: Word) {
+        self.pc = addr;
+    }
+
+    // Opcode of the instruction started by the last tick that returned true,
+    // or None if that tick was spent on entering an interrupt.
+    pub fn last_opcode(&self) -> Option<Byte> {
+        self.last_opcode
+    }
+
     fn read_word(&self, mem: &dyn Memory, addr: Word) -> Word {
         let lo = mem.read(addr) as Word;
         let hi = mem.read(addr + 1) as Word;
@@ -973,20 +1016,42 @@ impl CPU {
         self.y = 0;
 
         self.cycles = 0;
+        self.nmi_pending = false;
     }
 
     pub fn tick(&mut self, mem: &mut dyn Memory) -> bool {
         if self.cycles > 0 {
+            if !self.rdy && self.cycles > self.trailing_writes {
+                return false;
+            }
             // Since we executed the opcode in one go, we just do nothing for the remaining cycles.
             self.cycles -= 1;
             return false;
         }
 
-        let opcode = mem.read(self.pc);
-        self.pc += 1;
+        // The next cycle is an opcode fetch, which is a read.
+        if !self.rdy {
+            return false;
+        }
 
         self.set_flag(FL_UNUSED, true); // should always be set
-        self.cycles = self.run_opcode(opcode, mem);
+
+        let mut mem = WriteTracker::new(mem);
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.last_opcode = None;
+            self.cycles = self.interrupt(&mut mem, VEC_NMI);
+        } else if self.irq && !self.read_flag(FL_NO_INTERRUPT) {
+            self.last_opcode = None;
+            self.cycles = self.interrupt(&mut mem, VEC_IRQ);
+        } else {
+            let opcode = mem.read(self.pc);
+            self.pc += 1;
+            self.last_opcode = Some(opcode);
+            self.cycles = self.run_opcode(opcode, &mut mem);
+        }
+
+        self.trailing_writes = mem.trailing_writes.get();
         true
     }
 }
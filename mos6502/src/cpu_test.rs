@@ -12,6 +12,32 @@ fn setup() -> (CPU, Ram) {
     (cpu, mem)
 }
 
+// Remembers the addresses that have been read.
+struct ReadLog {
+    ram: Ram,
+    reads: std::cell::RefCell<Vec<Word>>,
+}
+
+impl ReadLog {
+    fn new(ram: Ram) -> Self {
+        Self {
+            ram,
+            reads: Default::default(),
+        }
+    }
+}
+
+impl Memory for ReadLog {
+    fn read(&self, addr: Word) -> Byte {
+        self.reads.borrow_mut().push(addr);
+        self.ram.read(addr)
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        self.ram.write(addr, data);
+    }
+}
+
 struct OpcodeTest {
     cpu: CPU,
     mem: Ram,
@@ -39,6 +65,13 @@ impl OpcodeTest {
         self.cpu.tick(&mut self.mem);
     }
 
+    // Runs the remaining cycles of the current instruction.
+    fn finish(&mut self) {
+        while self.cpu.cycles > 0 {
+            self.cpu.tick(&mut self.mem);
+        }
+    }
+
     fn assert_cycles(&self, n: u8) {
         assert_eq!(n, self.cpu.cycles, "cycles={}, want {}", self.cpu.cycles, n);
     }
@@ -91,11 +124,11 @@ impl OpcodeTest {
     }
 
     fn assert_flag_set(&self, fl: Flag) {
-        assert_eq!(true, self.cpu.read_flag(fl), "flag {} not set", fl);
+        assert!(self.cpu.read_flag(fl), "flag {} not set", fl);
     }
 
     fn assert_flag_unset(&self, fl: Flag) {
-        assert_eq!(false, self.cpu.read_flag(fl), "flag {} set", fl);
+        assert!(!self.cpu.read_flag(fl), "flag {} set", fl);
     }
 }
 
@@ -210,6 +243,44 @@ mod lda_test {
 mod sta_test {
     use super::*;
 
+    #[test]
+    fn stores_do_not_read_target() {
+        let stores = [
+            (OP_STA_ZP0, 0x0040, 0x0040),
+            (OP_STA_ZPX, 0x0040, 0x0041),
+            (OP_STA_ABS, 0x0400, 0x0400),
+            (OP_STA_ABX, 0x0400, 0x0401),
+            (OP_STA_ABY, 0x0400, 0x0402),
+            (OP_STA_IDX, 0x001F, 0x0400), // pointer at 0x20
+            (OP_STA_IDY, 0x0020, 0x0402),
+            (OP_STX_ZP0, 0x0040, 0x0040),
+            (OP_STX_ZPY, 0x0040, 0x0042),
+            (OP_STX_ABS, 0x0400, 0x0400),
+            (OP_STY_ZP0, 0x0040, 0x0040),
+            (OP_STY_ZPX, 0x0040, 0x0041),
+            (OP_STY_ABS, 0x0400, 0x0400),
+        ];
+        for (opcode, operand, target) in stores {
+            let (mut cpu, mem) = setup();
+            let mut mem = ReadLog::new(mem);
+            cpu.x = 0x01;
+            cpu.y = 0x02;
+            mem.write(0x0020, 0x00);
+            mem.write(0x0021, 0x04);
+            mem.write(0xFF00, opcode);
+            mem.write(0xFF01, operand as Byte);
+            mem.write(0xFF02, (operand >> 8) as Byte);
+
+            cpu.tick(&mut mem);
+            assert!(
+                !mem.reads.borrow().contains(&target),
+                "opcode 0x{:02X} read 0x{:04X}",
+                opcode,
+                target
+            );
+        }
+    }
+
     #[test]
     fn sta_zp() {
         let (mut cpu, mut mem) = setup();
@@ -1710,6 +1781,31 @@ mod ror_test {
     });
 }
 
+mod acc_test {
+    use super::*;
+
+    // The accumulator has no address, so these never get to compute one.
+    #[test]
+    fn acc_does_not_address_memory() {
+        let ops = [
+            (OP_ASL_ACC, 0x82),
+            (OP_LSR_ACC, 0x20),
+            (OP_ROL_ACC, 0x82),
+            (OP_ROR_ACC, 0x20),
+        ];
+        for (opcode, result) in ops {
+            let (mut cpu, mem) = setup();
+            let mut mem = ReadLog::new(mem);
+            cpu.a = 0x41;
+            mem.write(0xFF00, opcode);
+
+            cpu.tick(&mut mem);
+            assert_eq!(cpu.a, result, "opcode 0x{:02X}", opcode);
+            assert_eq!(*mem.reads.borrow(), vec![0xFF00], "opcode 0x{:02X}", opcode);
+        }
+    }
+}
+
 mod brk_test {
     use super::*;
 
@@ -1751,3 +1847,147 @@ mod rti_test {
         t.assert_flag_set(FL_NEGATIVE);
     });
 }
+
+mod interrupt_test {
+    use super::*;
+
+    opcode_test!(irq, |mut t: OpcodeTest| {
+        t.mem.write(0xFFFE, 0xCD);
+        t.mem.write(0xFFFF, 0xAB);
+        t.cpu.set_flag(FL_ZERO, true);
+
+        t.cpu.set_irq(true);
+        t.cpu.tick(&mut t.mem);
+        t.assert_cycles(7);
+        t.assert_pc(0xABCD);
+        assert_eq!(t.cpu.last_opcode(), None);
+        t.assert_sp(0xFC);
+        t.assert_flag_set(FL_NO_INTERRUPT);
+
+        // return address is on stack
+        t.assert_mem(0x01FF, 0x00);
+        t.assert_mem(0x01FE, 0xFF);
+
+        // status register is on stack, with the break flag cleared
+        t.assert_mem(0x01FD, 0b00100010);
+    });
+
+    opcode_test!(irq_masked, |mut t: OpcodeTest| {
+        t.cpu.set_flag(FL_NO_INTERRUPT, true);
+        t.cpu.set_irq(true);
+
+        t.exec(OP_NOP, 0);
+        t.assert_pc(0xFF01);
+        t.assert_sp(0xFF);
+        assert_eq!(t.cpu.last_opcode(), Some(OP_NOP));
+    });
+
+    opcode_test!(irq_rti, |mut t: OpcodeTest| {
+        t.mem.write(0xFFFE, 0x00);
+        t.mem.write(0xFFFF, 0x80);
+        t.mem.write(0x8000, OP_RTI);
+
+        t.cpu.set_irq(true);
+        t.cpu.tick(&mut t.mem);
+        t.cpu.set_irq(false);
+        t.finish();
+        t.cpu.tick(&mut t.mem);
+
+        t.assert_pc(0xFF00);
+        t.assert_sp(0xFF);
+        t.assert_flag_unset(FL_NO_INTERRUPT);
+    });
+
+    opcode_test!(nmi, |mut t: OpcodeTest| {
+        t.mem.write(0xFFFA, 0x34);
+        t.mem.write(0xFFFB, 0x12);
+        t.cpu.set_flag(FL_NO_INTERRUPT, true);
+
+        t.cpu.set_nmi(true);
+        t.cpu.tick(&mut t.mem);
+        t.assert_cycles(7);
+        t.assert_pc(0x1234);
+        t.assert_sp(0xFC);
+    });
+
+    opcode_test!(nmi_edge, |mut t: OpcodeTest| {
+        t.mem.write(0xFFFA, 0x34);
+        t.mem.write(0xFFFB, 0x12);
+        t.mem.write(0x1234, OP_NOP);
+
+        t.cpu.set_nmi(true);
+        t.cpu.tick(&mut t.mem);
+        t.finish();
+
+        // the line is still asserted, but there is no new edge
+        t.cpu.set_nmi(true);
+        t.cpu.tick(&mut t.mem);
+        t.assert_pc(0x1235);
+        t.assert_sp(0xFC);
+    });
+}
+
+mod rdy_test {
+    use super::*;
+
+    opcode_test!(rdy_halts_fetch, |mut t: OpcodeTest| {
+        t.mem.write(0xFF00, OP_NOP);
+        t.cpu.set_rdy(false);
+
+        assert!(!t.cpu.tick(&mut t.mem));
+        t.assert_pc(0xFF00);
+
+        t.cpu.set_rdy(true);
+        assert!(t.cpu.tick(&mut t.mem));
+        t.assert_pc(0xFF01);
+    });
+
+    opcode_test!(rdy_completes_writes, |mut t: OpcodeTest| {
+        t.exec(OP_STA_ABS, 0x0200);
+        t.assert_cycles(4);
+        t.cpu.set_rdy(false);
+
+        // read cycles are stalled
+        t.cpu.tick(&mut t.mem);
+        t.assert_cycles(4);
+
+        // the final write cycle is not
+        t.cpu.set_rdy(true);
+        for _ in 0..3 {
+            t.cpu.tick(&mut t.mem);
+        }
+        t.assert_cycles(1);
+        t.cpu.set_rdy(false);
+        t.cpu.tick(&mut t.mem);
+        t.assert_cycles(0);
+    });
+}
+
+mod so_test {
+    use super::*;
+
+    opcode_test!(so_sets_overflow, |mut t: OpcodeTest| {
+        t.cpu.set_so(true);
+        t.assert_flag_set(FL_OVERFLOW);
+
+        // the flag is only set on the edge, so it can be cleared while the line is held
+        t.exec(OP_CLV_IMP, 0);
+        t.cpu.set_so(true);
+        t.assert_flag_unset(FL_OVERFLOW);
+
+        t.cpu.set_so(false);
+        t.cpu.set_so(true);
+        t.assert_flag_set(FL_OVERFLOW);
+    });
+
+    opcode_test!(so_bvc_loop, |mut t: OpcodeTest| {
+        // BVC * spins until the byte is ready
+        t.exec(OP_BVC_REL, 0xFE);
+        t.assert_pc(0xFF00);
+
+        t.cpu.set_so(true);
+        t.finish();
+        t.cpu.tick(&mut t.mem);
+        t.assert_pc(0xFF02);
+    });
+}
@@ -0,0 +1,135 @@
+use crate::image::{decode_hex, Image};
+use crate::types::*;
+
+const REC_DATA: Byte = 0x00;
+const REC_EOF: Byte = 0x01;
+const REC_EXT_SEGMENT_ADDR: Byte = 0x02;
+const REC_START_SEGMENT_ADDR: Byte = 0x03;
+const REC_EXT_LINEAR_ADDR: Byte = 0x04;
+const REC_START_LINEAR_ADDR: Byte = 0x05;
+
+// Parses an Intel HEX file. Each record looks like `:LLAAAATTDD..DDCC`, where LL is the number
+// of data bytes, AAAA is the address, TT is the record type and CC is the two's complement of
+// the sum of all the other bytes. The start address records set the entry point of the image.
+pub fn parse(text: &str) -> Result<Image, String> {
+    let mut image = Image::default();
+    let mut base: u32 = 0;
+    let mut eof = false;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let err = |msg: &str| format!("line {}: {}", n + 1, msg);
+        if eof {
+            return Err(err("record after the end of file"));
+        }
+
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| err("record must start with ':'"))?;
+        let bytes = decode_hex(hex).map_err(|e| err(&e))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(err("invalid record length"));
+        }
+
+        let checksum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        if checksum != 0 {
+            return Err(err("checksum mismatch"));
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+
+        match bytes[3] {
+            REC_DATA => {
+                let addr = base + offset;
+                if addr > 0xFFFF {
+                    return Err(err("address is out of the 64K address space"));
+                }
+                image.push(addr as Word, data).map_err(|e| err(&e))?;
+            }
+            REC_EOF => eof = true,
+            REC_EXT_SEGMENT_ADDR | REC_EXT_LINEAR_ADDR => {
+                if data.len() != 2 {
+                    return Err(err("invalid extended address record"));
+                }
+                let value = u16::from_be_bytes([data[0], data[1]]) as u32;
+                base = if bytes[3] == REC_EXT_SEGMENT_ADDR {
+                    value << 4
+                } else {
+                    value << 16
+                };
+            }
+            REC_START_SEGMENT_ADDR | REC_START_LINEAR_ADDR => {
+                if data.len() != 4 {
+                    return Err(err("invalid start address record"));
+                }
+                let entry = if bytes[3] == REC_START_SEGMENT_ADDR {
+                    let cs = u16::from_be_bytes([data[0], data[1]]) as u32;
+                    let ip = u16::from_be_bytes([data[2], data[3]]) as u32;
+                    (cs << 4) + ip
+                } else {
+                    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+                };
+                let entry =
+                    Word::try_from(entry).map_err(|_| err("start address is out of range"))?;
+                image.entry = Some(entry);
+            }
+            _ => return Err(err("unknown record type")),
+        }
+    }
+
+    if !eof {
+        return Err("missing end of file record".to_string());
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod ihex_test {
+    use super::*;
+
+    #[test]
+    fn parse_data() {
+        let image = parse(
+            ":03030000A90A8DBA\n\
+             :020303000002F6\n\
+             :02FFFC00000300\n\
+             :00000001FF\n",
+        )
+        .unwrap();
+
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].addr, 0x0300);
+        assert_eq!(image.segments[0].data, vec![0xA9, 0x0A, 0x8D, 0x00, 0x02]);
+        assert_eq!(image.segments[1].addr, 0xFFFC);
+        assert_eq!(image.segments[1].data, vec![0x00, 0x03]);
+        assert_eq!(image.entry, None);
+    }
+
+    #[test]
+    fn parse_entry() {
+        let image = parse(":040000050000800077\n:00000001FF\n").unwrap();
+        assert_eq!(image.entry, Some(0x8000));
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let err = parse(":03030000A90A8DBB\n:00000001FF\n").unwrap_err();
+        assert_eq!(err, "line 1: checksum mismatch");
+    }
+
+    #[test]
+    fn address_out_of_range() {
+        let err = parse(":020000040001F9\n:01000000EA15\n:00000001FF\n").unwrap_err();
+        assert_eq!(err, "line 2: address is out of the 64K address space");
+    }
+
+    #[test]
+    fn missing_eof() {
+        assert!(parse(":03030000A90A8DBA\n").is_err());
+    }
+}
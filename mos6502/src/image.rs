@@ -0,0 +1,185 @@
+use crate::bus::{Bus, DeviceRef};
+use crate::mem::{Device, Rom};
+use crate::o65;
+use crate::types::*;
+
+// A contiguous block of data to be placed at the given address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: Word,
+    pub data: Vec<Byte>,
+}
+
+impl Segment {
+    // Address right past the last byte of the segment.
+    pub fn end(&self) -> usize {
+        self.addr as usize + self.data.len()
+    }
+}
+
+// Program image made of sparse segments, as produced by assemblers and EEPROM toolchains.
+// Some formats also tell where the execution should start.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub entry: Option<Word>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Binary,
+    IntelHex,
+    SRecord,
+    O65,
+    // PRG files have no magic number, so they are never detected by the contents.
+    Prg,
+}
+
+// Guesses the format of an image by its contents. Anything that does not look like
+// one of the known formats is considered to be a raw binary.
+pub fn detect(data: &[Byte]) -> Format {
+    if o65::is_o65(data) {
+        return Format::O65;
+    }
+
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text.trim_start(),
+        Err(_) => return Format::Binary,
+    };
+
+    let is_text = |prefix: fn(&str) -> bool| {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .all(|line| prefix(line) && line[1..].chars().all(|c| c.is_ascii_alphanumeric()))
+    };
+
+    if text.starts_with(':') && is_text(|line| line.starts_with(':')) {
+        Format::IntelHex
+    } else if text.starts_with('S') && is_text(|line| line.starts_with('S')) {
+        Format::SRecord
+    } else {
+        Format::Binary
+    }
+}
+
+impl Image {
+    // Image of a raw binary file that is loaded at the given address.
+    pub fn binary(addr: Word, data: &[Byte]) -> Result<Image, String> {
+        let mut image = Image::default();
+        image.push(addr, data)?;
+        Ok(image)
+    }
+
+    // Adds the data to the image, extending the last segment if the data directly follows it.
+    pub fn push(&mut self, addr: Word, data: &[Byte]) -> Result<(), String> {
+        if addr as usize + data.len() > 0x10000 {
+            return Err(format!(
+                "{} bytes at 0x{:04X} do not fit into the address space",
+                data.len(),
+                addr
+            ));
+        }
+
+        match self.segments.last_mut() {
+            Some(last) if last.end() == addr as usize => last.data.extend_from_slice(data),
+            _ => self.segments.push(Segment {
+                addr,
+                data: data.to_vec(),
+            }),
+        }
+        Ok(())
+    }
+
+    // Total number of bytes in all segments.
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|seg| seg.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Writes all segments into memory through the bus, bypassing write protection.
+    pub fn load_into<D: DeviceRef>(&self, bus: &mut Bus<D>) -> Result<(), String> {
+        for seg in &self.segments {
+            bus.load(seg.addr, &seg.data)?;
+        }
+        Ok(())
+    }
+
+    // Burns the image into a ROM chip of the given size that is mapped at the given address.
+    // The rest of the chip is filled with the given byte.
+    pub fn to_rom(&self, start: Word, size: usize, fill: Byte) -> Result<Rom, String> {
+        let mut rom = Rom::new(&vec![fill; size]);
+        for seg in &self.segments {
+            if seg.addr < start || seg.end() > start as usize + size {
+                return Err(format!(
+                    "segment 0x{:04X}-0x{:04X} does not fit into the rom",
+                    seg.addr,
+                    seg.end() - 1
+                ));
+            }
+            for (i, &byte) in seg.data.iter().enumerate() {
+                rom.poke(seg.addr - start + i as Word, byte);
+            }
+        }
+        Ok(rom)
+    }
+}
+
+// Decodes a string of hex digits into bytes.
+pub(crate) fn decode_hex(s: &str) -> Result<Vec<Byte>, String> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(format!("invalid hex string: {}", s));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            Byte::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("invalid hex string: {}", s))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod image_test {
+    use super::*;
+    use crate::mem::Memory;
+
+    #[test]
+    fn push_merges_segments() {
+        let mut image = Image::default();
+        image.push(0x1000, &[1, 2]).unwrap();
+        image.push(0x1002, &[3]).unwrap();
+        image.push(0x2000, &[4]).unwrap();
+
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].data, vec![1, 2, 3]);
+        assert_eq!(image.len(), 4);
+        assert!(image.push(0xFFFF, &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(detect(b":0100000001FE\n:00000001FF\n"), Format::IntelHex);
+        assert_eq!(detect(b"S1040000FFFC\nS9030000FC\n"), Format::SRecord);
+        assert_eq!(detect(b"\x01\x00o65\x00"), Format::O65);
+        assert_eq!(detect(&[0xA9, 0x00, 0x8D]), Format::Binary);
+        assert_eq!(detect(b"Some text"), Format::Binary);
+    }
+
+    #[test]
+    fn to_rom() {
+        let mut image = Image::default();
+        image.push(0xFFFC, &[0x00, 0x80]).unwrap();
+        image.push(0x8000, &[0xEA]).unwrap();
+
+        let rom = image.to_rom(0x8000, 0x8000, 0xFF).unwrap();
+        assert_eq!(rom.read(0x0000), 0xEA);
+        assert_eq!(rom.read(0x0001), 0xFF);
+        assert_eq!(rom.read(0x7FFC), 0x00);
+        assert_eq!(rom.read(0x7FFD), 0x80);
+
+        assert!(image.to_rom(0xC000, 0x4000, 0xFF).is_err());
+    }
+}
@@ -0,0 +1,557 @@
+use crate::types::*;
+
+// Execution times of the instructions, in microseconds.
+const CLEAR_TIME: f32 = 1520.0;
+const INSTRUCTION_TIME: f32 = 37.0;
+
+const LINE_LEN: usize = 40; // characters per line in the two-line mode
+const LINE_2: usize = 0x40; // DDRAM address of the second line
+
+// Hitachi HD44780 character LCD controller, as found on the ubiquitous 16x2 modules.
+// It is driven at the pin level: register select, read/write and enable, and eight data lines,
+// of which only D4-D7 are used in the 4-bit mode. Commands and data are latched on the falling
+// edge of E, and reads put the data on the lines while E is high.
+pub struct Lcd {
+    columns: usize,
+    rows: usize,
+    cycles_per_us: f32,
+
+    ddram: [Byte; 0x80],
+    cgram: [Byte; 0x40],
+    addr: usize,      // address counter
+    cgram_mode: bool, // the address counter points into CGRAM
+    increment: bool,
+    entry_shift: bool,
+    display: bool,
+    cursor: bool,
+    blink: bool,
+    shift: usize, // how far the display is shifted to the left
+    eight_bit: bool,
+    two_lines: bool,
+
+    nibble: Option<Byte>, // high nibble of a write in the 4-bit mode
+    read_low: bool,       // next read in the 4-bit mode returns the low nibble
+    read_value: Byte,
+    busy: u64, // cycles until the current instruction is done
+
+    rs: bool,
+    rw: bool,
+    e: bool,
+    data: Byte,
+    output: Option<Byte>,
+}
+
+impl Lcd {
+    // The busy flag is timed in CPU cycles, so the frequency of the CPU is needed (in MHz).
+    pub fn new(cpu_frequency: f32) -> Lcd {
+        Lcd {
+            columns: 16,
+            rows: 2,
+            cycles_per_us: cpu_frequency,
+            ddram: [b' '; 0x80],
+            cgram: [0; 0x40],
+            addr: 0,
+            cgram_mode: false,
+            increment: true,
+            entry_shift: false,
+            display: false,
+            cursor: false,
+            blink: false,
+            shift: 0,
+            eight_bit: true,
+            two_lines: false,
+            nibble: None,
+            read_low: false,
+            read_value: 0,
+            busy: 0,
+            rs: false,
+            rw: false,
+            e: false,
+            data: 0,
+            output: None,
+        }
+    }
+
+    // Size of the visible area, 16x2 by default. Four-line modules are two lines
+    // of the controller split in halves, so rows 3 and 4 continue rows 1 and 2.
+    pub fn with_size(mut self, columns: usize, rows: usize) -> Lcd {
+        self.columns = columns;
+        self.rows = rows;
+        self
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn busy(&self) -> bool {
+        self.busy > 0
+    }
+
+    // Sets the levels of the data lines D0-D7.
+    pub fn set_data(&mut self, data: Byte) {
+        self.data = data;
+    }
+
+    // Sets the levels of the RS, R/W and E lines.
+    pub fn set_control(&mut self, rs: bool, rw: bool, e: bool) {
+        let rising = e && !self.e;
+        let falling = !e && self.e;
+        self.rs = rs;
+        self.rw = rw;
+        self.e = e;
+
+        if rising && rw {
+            self.output = Some(self.read());
+        }
+        if falling {
+            self.output = None;
+            if !rw {
+                self.write();
+            }
+        }
+    }
+
+    // Levels the LCD puts on the data lines while it is being read.
+    // In the 4-bit mode only D4-D7 are driven.
+    pub fn output(&self) -> Option<Byte> {
+        self.output
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        self.busy = self.busy.saturating_sub(cycles);
+    }
+
+    fn start(&mut self, us: f32) {
+        self.busy = (us * self.cycles_per_us).ceil() as u64;
+    }
+
+    fn read(&mut self) -> Byte {
+        if !self.eight_bit && self.read_low {
+            self.read_low = false;
+            return (self.read_value << 4) | 0x0F;
+        }
+
+        let value = if self.rs {
+            let addr = self.addr;
+            let value = self.ram()[addr];
+            self.advance();
+            value
+        } else {
+            ((self.busy() as Byte) << 7) | (self.addr as Byte & 0x7F)
+        };
+
+        if self.eight_bit {
+            value
+        } else {
+            self.read_low = true;
+            self.read_value = value;
+            value | 0x0F
+        }
+    }
+
+    fn write(&mut self) {
+        let value = if self.eight_bit {
+            self.data
+        } else {
+            match self.nibble.take() {
+                Some(high) => high | self.data >> 4,
+                None => {
+                    self.nibble = Some(self.data & 0xF0);
+                    return;
+                }
+            }
+        };
+        self.read_low = false;
+
+        if self.rs {
+            let addr = self.addr;
+            self.ram()[addr] = value;
+            self.advance();
+            if self.entry_shift && !self.cgram_mode {
+                self.shift_display(self.increment);
+            }
+            self.start(INSTRUCTION_TIME);
+        } else {
+            self.instruction(value);
+        }
+    }
+
+    fn instruction(&mut self, cmd: Byte) {
+        self.start(INSTRUCTION_TIME);
+
+        match cmd.leading_zeros() {
+            // Set DDRAM address
+            0 => {
+                self.cgram_mode = false;
+                self.addr = (cmd & 0x7F) as usize;
+            }
+            // Set CGRAM address
+            1 => {
+                self.cgram_mode = true;
+                self.addr = (cmd & 0x3F) as usize;
+            }
+            // Function set
+            2 => {
+                self.eight_bit = cmd & 0x10 != 0;
+                self.two_lines = cmd & 0x08 != 0;
+                self.nibble = None;
+            }
+            // Cursor or display shift
+            3 => {
+                let left = cmd & 0x04 == 0;
+                if cmd & 0x08 != 0 {
+                    self.shift_display(left);
+                } else {
+                    let increment = self.increment;
+                    self.increment = !left;
+                    self.advance();
+                    self.increment = increment;
+                }
+            }
+            // Display on/off control
+            4 => {
+                self.display = cmd & 0x04 != 0;
+                self.cursor = cmd & 0x02 != 0;
+                self.blink = cmd & 0x01 != 0;
+            }
+            // Entry mode set
+            5 => {
+                self.increment = cmd & 0x02 != 0;
+                self.entry_shift = cmd & 0x01 != 0;
+            }
+            // Return home
+            6 => {
+                self.start(CLEAR_TIME);
+                self.cgram_mode = false;
+                self.addr = 0;
+                self.shift = 0;
+            }
+            // Clear display
+            7 => {
+                self.start(CLEAR_TIME);
+                self.ddram = [b' '; 0x80];
+                self.cgram_mode = false;
+                self.addr = 0;
+                self.shift = 0;
+                self.increment = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn ram(&mut self) -> &mut [Byte] {
+        if self.cgram_mode {
+            &mut self.cgram
+        } else {
+            &mut self.ddram
+        }
+    }
+
+    // Moves the address counter. In the two-line mode the lines are 40 characters long
+    // and the counter jumps from the end of one to the start of the other. An address
+    // set outside of the lines wraps around at the end of the DDRAM.
+    fn advance(&mut self) {
+        let addr = self.addr;
+        self.addr = if self.cgram_mode {
+            if self.increment {
+                (addr + 1) % self.cgram.len()
+            } else {
+                (addr + self.cgram.len() - 1) % self.cgram.len()
+            }
+        } else if self.two_lines {
+            match (self.increment, addr) {
+                (true, 0x27) => 0x40,
+                (true, 0x67) => 0x00,
+                (true, _) => (addr + 1) % self.ddram.len(),
+                (false, 0x00) => 0x67,
+                (false, 0x40) => 0x27,
+                (false, _) => addr - 1,
+            }
+        } else {
+            match (self.increment, addr) {
+                (true, 0x4F) => 0x00,
+                (true, _) => (addr + 1) % self.ddram.len(),
+                (false, 0x00) => 0x4F,
+                (false, _) => addr - 1,
+            }
+        };
+    }
+
+    fn line_len(&self) -> usize {
+        if self.two_lines {
+            LINE_LEN
+        } else {
+            2 * LINE_LEN
+        }
+    }
+
+    fn shift_display(&mut self, left: bool) {
+        let len = self.line_len();
+        self.shift = if left {
+            (self.shift + 1) % len
+        } else {
+            (self.shift + len - 1) % len
+        };
+    }
+
+    // Character codes of the visible area, row by row, or None if the display is off.
+    pub fn codes(&self) -> Option<Vec<Vec<Byte>>> {
+        if !self.display {
+            return None;
+        }
+        let len = self.line_len();
+        let lines = if self.two_lines { 2 } else { 1 };
+        let rows = (0..self.rows)
+            .map(|row| {
+                let line = row % 2;
+                let offset = row / 2 * self.columns;
+                (0..self.columns)
+                    .map(|col| {
+                        if line < lines {
+                            self.ddram[line * LINE_2 + (offset + col + self.shift) % len]
+                        } else {
+                            b' '
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Some(rows)
+    }
+
+    // Text on the display, with the characters of the standard A00 ROM that have
+    // no ASCII equivalent approximated and the user-defined ones shown as blocks.
+    pub fn screen(&self) -> Vec<String> {
+        match self.codes() {
+            Some(rows) => rows
+                .iter()
+                .map(|row| row.iter().map(|&code| glyph(code)).collect())
+                .collect(),
+            None => vec![" ".repeat(self.columns); self.rows],
+        }
+    }
+
+    // Position of the cursor on the screen, if it is shown there.
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        if !self.display || !(self.cursor || self.blink) || self.cgram_mode {
+            return None;
+        }
+        let len = self.line_len();
+        let (line, pos) = if self.two_lines {
+            (self.addr / LINE_2, self.addr % LINE_2)
+        } else {
+            (0, self.addr)
+        };
+        let col = (pos + len - self.shift) % len;
+        (0..self.rows)
+            .filter(|row| row % 2 == line)
+            .map(|row| (row, col.wrapping_sub(row / 2 * self.columns)))
+            .find(|&(_, col)| col < self.columns)
+    }
+}
+
+fn glyph(code: Byte) -> char {
+    match code {
+        0x00..=0x0F => '█',
+        0x5C => '¥',
+        0x7E => '→',
+        0x7F => '←',
+        0xDF => '°',
+        0x20..=0x7D => code as char,
+        _ => '?',
+    }
+}
+
+#[cfg(test)]
+mod lcd_test {
+    use super::*;
+
+    fn write(lcd: &mut Lcd, rs: bool, data: Byte) {
+        lcd.set_data(data);
+        lcd.set_control(rs, false, true);
+        lcd.set_control(rs, false, false);
+        lcd.tick(2000);
+    }
+
+    fn read(lcd: &mut Lcd, rs: bool) -> Byte {
+        lcd.set_control(rs, true, true);
+        let data = lcd.output().unwrap();
+        lcd.set_control(rs, true, false);
+        data
+    }
+
+    fn print(lcd: &mut Lcd, text: &str) {
+        for b in text.bytes() {
+            write(lcd, true, b);
+        }
+    }
+
+    fn init() -> Lcd {
+        let mut lcd = Lcd::new(1.0);
+        write(&mut lcd, false, 0b0011_1000); // 8-bit, 2 lines, 5x8
+        write(&mut lcd, false, 0b0000_1110); // display on, cursor on, blink off
+        write(&mut lcd, false, 0b0000_0110); // increment, no shift
+        write(&mut lcd, false, 0b0000_0001); // clear
+        lcd
+    }
+
+    #[test]
+    fn hello_world() {
+        let mut lcd = init();
+        print(&mut lcd, "Hello, world!");
+        write(&mut lcd, false, 0x80 | 0x40);
+        print(&mut lcd, "6502");
+        assert_eq!(lcd.screen(), vec!["Hello, world!   ", "6502            "]);
+        assert_eq!(lcd.cursor(), Some((1, 4)));
+    }
+
+    #[test]
+    fn display_off() {
+        let mut lcd = Lcd::new(1.0);
+        print(&mut lcd, "hidden");
+        assert_eq!(lcd.screen(), vec![" ".repeat(16); 2]);
+        assert_eq!(lcd.codes(), None);
+    }
+
+    #[test]
+    fn busy_flag() {
+        let mut lcd = init();
+        lcd.set_data(0x01);
+        lcd.set_control(false, false, true);
+        lcd.set_control(false, false, false);
+
+        assert_eq!(read(&mut lcd, false) & 0x80, 0x80);
+        lcd.tick(1519);
+        assert_eq!(read(&mut lcd, false) & 0x80, 0x80);
+        lcd.tick(1);
+        assert_eq!(read(&mut lcd, false), 0x00);
+
+        // everything else takes 37 µs, twice as many cycles at 2 MHz
+        let mut lcd = Lcd::new(2.0);
+        lcd.set_data(b'A');
+        lcd.set_control(true, false, true);
+        lcd.set_control(true, false, false);
+        lcd.tick(73);
+        assert!(lcd.busy());
+        lcd.tick(1);
+        assert!(!lcd.busy());
+    }
+
+    #[test]
+    fn address_counter() {
+        let mut lcd = init();
+        print(&mut lcd, "abc");
+        assert_eq!(read(&mut lcd, false), 3);
+
+        // the first line ends at 0x27, then the second one starts
+        write(&mut lcd, false, 0x80 | 0x27);
+        print(&mut lcd, "xy");
+        assert_eq!(read(&mut lcd, false), 0x41);
+
+        write(&mut lcd, false, 0x80);
+        assert_eq!(read(&mut lcd, true), b'a');
+        assert_eq!(read(&mut lcd, true), b'b');
+    }
+
+    #[test]
+    fn decrement_and_cursor_shift() {
+        let mut lcd = init();
+        write(&mut lcd, false, 0b0000_0100); // decrement
+        write(&mut lcd, false, 0x80 | 0x02);
+        print(&mut lcd, "cba");
+        assert_eq!(lcd.screen()[0], "abc             ");
+
+        write(&mut lcd, false, 0b0001_0100); // move the cursor right
+        assert_eq!(read(&mut lcd, false), 0x00);
+        write(&mut lcd, false, 0b0001_0000); // and back left
+        assert_eq!(read(&mut lcd, false), 0x67);
+    }
+
+    #[test]
+    fn display_shift() {
+        let mut lcd = init();
+        print(&mut lcd, "0123456789ABCDEFGH");
+        write(&mut lcd, false, 0b0001_1000); // shift left
+        write(&mut lcd, false, 0b0001_1000);
+        assert_eq!(lcd.screen()[0], "23456789ABCDEFGH");
+
+        write(&mut lcd, false, 0b0000_0010); // home
+        assert_eq!(lcd.screen()[0], "0123456789ABCDEF");
+
+        // shifting on entry keeps the cursor in place
+        write(&mut lcd, false, 0b0000_0111);
+        write(&mut lcd, false, 0x80 | 0x10);
+        print(&mut lcd, "!!");
+        assert_eq!(lcd.screen()[0], "23456789ABCDEF!!");
+    }
+
+    #[test]
+    fn four_bit_mode() {
+        let mut lcd = Lcd::new(1.0);
+        let nibble = |lcd: &mut Lcd, rs, n: Byte| write(lcd, rs, n << 4);
+
+        // function set to 4 bits is a single write, as only D4-D7 are connected
+        nibble(&mut lcd, false, 0b0010);
+        for cmd in [0b0010_1000, 0b0000_1100, 0b0000_0110, 0b0000_0001] {
+            nibble(&mut lcd, false, cmd >> 4);
+            nibble(&mut lcd, false, cmd & 0x0F);
+        }
+        for b in "4 bits".bytes() {
+            nibble(&mut lcd, true, b >> 4);
+            nibble(&mut lcd, true, b & 0x0F);
+        }
+        assert_eq!(lcd.screen()[0], "4 bits          ");
+
+        // reads return the high nibble first
+        let high = read(&mut lcd, false) >> 4;
+        let low = read(&mut lcd, false) >> 4;
+        assert_eq!(high << 4 | low, 6);
+    }
+
+    #[test]
+    fn custom_characters() {
+        let mut lcd = init();
+        write(&mut lcd, false, 0x40 | 0x08); // character 1
+        for row in [0x00, 0x0A, 0x00, 0x11, 0x0E, 0x00, 0x00, 0x00] {
+            write(&mut lcd, true, row);
+        }
+        write(&mut lcd, false, 0x80);
+        write(&mut lcd, true, 0x01);
+        assert_eq!(lcd.cgram[0x0B], 0x11);
+        assert_eq!(lcd.codes().unwrap()[0][0], 0x01);
+        assert_eq!(lcd.screen()[0], "█               ");
+    }
+
+    #[test]
+    fn four_rows() {
+        let mut lcd = init().with_size(20, 4);
+        print(&mut lcd, "first row           third row");
+        write(&mut lcd, false, 0x80 | 0x40);
+        print(&mut lcd, "second row          fourth row");
+        assert_eq!(
+            lcd.screen(),
+            vec![
+                "first row           ",
+                "second row          ",
+                "third row           ",
+                "fourth row          ",
+            ]
+        );
+        assert_eq!(lcd.cursor(), Some((3, 10)));
+    }
+
+    #[test]
+    fn address_wraps() {
+        let mut lcd = init();
+        write(&mut lcd, false, 0xFF);
+        print(&mut lcd, "ab");
+        assert_eq!(read(&mut lcd, false), 0x01);
+        assert_eq!(lcd.ddram[0x7F], b'a');
+        assert_eq!(lcd.ddram[0x00], b'b');
+    }
+}
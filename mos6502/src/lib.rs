@@ -1,6 +1,16 @@
+pub mod acia;
 pub mod bus;
 pub mod clock;
 pub mod cpu;
+pub mod ihex;
+pub mod image;
+pub mod lcd;
 pub mod mem;
+pub mod o65;
 pub mod opcodes;
+pub mod prg;
+pub mod psg;
+pub mod riot;
+pub mod srec;
 pub mod types;
+pub mod via;
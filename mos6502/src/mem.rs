@@ -1,4 +1,7 @@
 use crate::types::*;
+use std::fs;
+use std::io;
+use std::path::Path;
 
 const RAM_SIZE: usize = 64 * 1024;
 
@@ -7,24 +10,449 @@ pub trait Memory {
     fn write(&mut self, addr: Word, data: Byte);
 }
 
-pub struct Ram {
-    data: [Byte; RAM_SIZE],
+// DMA transfer requested by a device. The bus copies the bytes one by one, with a read cycle followed
+// by a write cycle, while the CPU is held off the bus with RDY. Before the transfer starts, one cycle
+// is spent waiting for the CPU to halt, plus one more to align to an even cycle. That makes a 256-byte
+// sprite DMA take 513 or 514 cycles, like on the real hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dma {
+    pub src: Word,
+    pub dst: Word,
+    pub len: u16,
+    pub increment_src: bool,
+    pub increment_dst: bool,
+}
+
+impl Dma {
+    // Copies a block of memory to another location.
+    pub fn copy(src: Word, dst: Word, len: u16) -> Dma {
+        Dma {
+            src,
+            dst,
+            len,
+            increment_src: true,
+            increment_dst: true,
+        }
+    }
+
+    // Streams a block of memory into a single data port, e.g. the sprite memory of a video chip.
+    pub fn to_port(src: Word, port: Word, len: u16) -> Dma {
+        Dma {
+            src,
+            dst: port,
+            len,
+            increment_src: true,
+            increment_dst: false,
+        }
+    }
+
+    // Fills a block of memory with the bytes read one by one from a single data port.
+    pub fn from_port(port: Word, dst: Word, len: u16) -> Dma {
+        Dma {
+            src: port,
+            dst,
+            len,
+            increment_src: false,
+            increment_dst: true,
+        }
+    }
+}
+
+// Device is anything that can be plugged into the bus. Unlike plain memory, a device may have
+// a life of its own: it is ticked together with the CPU, so timers, serial ports or video chips
+// can advance with the emulated time, it can pull the IRQ, NMI, RDY and SO lines, and it can ask
+// the bus to make a DMA transfer. Passive devices just rely on the default implementation.
+pub trait Device: Memory {
+    fn tick(&mut self, _cycles: u64) {}
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    fn rdy(&self) -> bool {
+        true
+    }
+
+    fn so(&self) -> bool {
+        false
+    }
+
+    fn take_dma(&mut self) -> Option<Dma> {
+        None
+    }
+
+    // Stores a byte bypassing any write protection. It is used to load images into memory,
+    // never by the CPU. Devices that are not memory treat it as a normal write.
+    fn poke(&mut self, addr: Word, data: Byte) {
+        self.write(addr, data);
+    }
+}
+
+// Random access memory of an arbitrary size. By default it is allocated on the heap, but it can also
+// wrap a buffer provided by the caller (anything that can be viewed as a byte slice, e.g. a `Vec` or
+// a `&mut [u8]`), so the backing storage can be shared or memory-mapped. Addresses past the end of
+// the buffer wrap around, so a 2K chip plugged into a 8K range is mirrored four times.
+pub struct Ram<B = Vec<Byte>> {
+    data: B,
+    protected: Vec<MemRange>,
 }
 
 impl Ram {
     pub fn new() -> Ram {
         Ram {
-            data: [0; RAM_SIZE],
+            data: vec![0; RAM_SIZE],
+            protected: Vec::new(),
         }
     }
+
+    pub fn with_size(size: usize) -> Result<Ram, String> {
+        Self::from_buffer(vec![0; size])
+    }
 }
 
-impl Memory for Ram {
+impl<B: AsRef<[Byte]> + AsMut<[Byte]>> Ram<B> {
+    pub fn from_buffer(data: B) -> Result<Ram<B>, String> {
+        if data.as_ref().is_empty() {
+            return Err("ram size must not be zero".to_string());
+        }
+        Ok(Ram {
+            data,
+            protected: Vec::new(),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.as_ref().len()
+    }
+
+    pub fn into_inner(self) -> B {
+        self.data
+    }
+
+    // Makes the given range read-only for the CPU. Writes that land in it are silently dropped,
+    // which is how a ROM chip sitting on the same bus would behave.
+    pub fn write_protect(&mut self, range: MemRange) {
+        self.protected.push(range);
+    }
+
+    // A range protects the cells it covers, so the mirrors of a protected cell are protected too.
+    fn is_protected(&self, addr: Word) -> bool {
+        let len = self.size();
+        let cell = addr as usize % len;
+        self.protected.iter().any(|&(start, end)| {
+            let span = end as usize - start as usize;
+            span + 1 >= len || (cell + len - start as usize % len) % len <= span
+        })
+    }
+
+    // Copies the data starting at the given address, ignoring write protection.
+    // This is the way to put a program into the protected part of the memory.
+    // Like any other access, it wraps around the end of the buffer.
+    pub fn load(&mut self, addr: Word, data: &[Byte]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.poke(addr.wrapping_add(i as Word), byte);
+        }
+    }
+}
+
+impl<B: AsRef<[Byte]> + AsMut<[Byte]>> Device for Ram<B> {
+    fn poke(&mut self, addr: Word, data: Byte) {
+        let buf = self.data.as_mut();
+        let len = buf.len();
+        buf[addr as usize % len] = data;
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: AsRef<[Byte]> + AsMut<[Byte]>> Memory for Ram<B> {
     fn read(&self, addr: Word) -> Byte {
-        self.data[addr as usize]
+        let data = self.data.as_ref();
+        data[addr as usize % data.len()]
     }
 
     fn write(&mut self, addr: Word, data: Byte) {
-        self.data[addr as usize] = data;
+        if self.is_protected(addr) {
+            return;
+        }
+        let buf = self.data.as_mut();
+        let len = buf.len();
+        buf[addr as usize % len] = data;
+    }
+}
+
+// Read-only memory initialized from an image. Writes are ignored. If the image is smaller than
+// the range it is plugged into, it repeats itself, the same way an undersized chip would be mirrored.
+pub struct Rom {
+    data: Vec<Byte>,
+}
+
+impl Rom {
+    pub fn new(data: &[Byte]) -> Rom {
+        Rom {
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Rom> {
+        let data = fs::read(path)?;
+        Ok(Rom { data })
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Memory for Rom {
+    fn read(&self, addr: Word) -> Byte {
+        if self.data.is_empty() {
+            return 0;
+        }
+        self.data[addr as usize % self.data.len()]
+    }
+
+    fn write(&mut self, _addr: Word, _data: Byte) {}
+}
+
+impl Device for Rom {
+    fn poke(&mut self, addr: Word, data: Byte) {
+        let len = self.data.len();
+        if len > 0 {
+            self.data[addr as usize % len] = data;
+        }
+    }
+}
+
+// Pages one of several banks into a window of the address space. The active bank is selected by
+// writing its number into the control register, placed at a configurable offset within the range
+// the device is plugged into. When the register is outside of the window it can also be read back.
+// When it is inside, it only latches writes and reads go to the bank, like in most cartridge mappers.
+pub struct BankedMemory {
+    banks: Vec<Box<dyn Memory + Send>>,
+    window: usize,
+    register: Word,
+    active: usize,
+}
+
+impl BankedMemory {
+    pub fn new(
+        banks: Vec<Box<dyn Memory + Send>>,
+        window: usize,
+        register: Word,
+    ) -> Result<BankedMemory, String> {
+        if banks.is_empty() {
+            return Err("at least one bank is required".to_string());
+        }
+        Ok(BankedMemory {
+            banks,
+            window,
+            register,
+            active: 0,
+        })
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.banks.len()
+    }
+
+    pub fn active_bank(&self) -> usize {
+        self.active
+    }
+
+    pub fn select(&mut self, bank: usize) {
+        self.active = bank % self.banks.len();
+    }
+
+    fn in_window(&self, addr: Word) -> bool {
+        (addr as usize) < self.window
+    }
+}
+
+impl Memory for BankedMemory {
+    fn read(&self, addr: Word) -> Byte {
+        if self.in_window(addr) {
+            self.banks[self.active].read(addr)
+        } else if addr == self.register {
+            self.active as Byte
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        if addr == self.register {
+            self.select(data as usize);
+        } else if self.in_window(addr) {
+            self.banks[self.active].write(addr, data);
+        }
+    }
+}
+
+impl Device for BankedMemory {}
+
+#[cfg(test)]
+mod mem_test {
+    use super::*;
+
+    #[test]
+    fn ram_write_protect() {
+        let mut ram = Ram::new();
+        ram.write_protect((0x8000, 0xFFFF));
+
+        ram.write(0x7FFF, 0xAA);
+        ram.write(0x8000, 0xBB);
+        ram.write(0xFFFF, 0xCC);
+
+        assert_eq!(ram.read(0x7FFF), 0xAA);
+        assert_eq!(ram.read(0x8000), 0x00);
+        assert_eq!(ram.read(0xFFFF), 0x00);
+    }
+
+    #[test]
+    fn ram_load_ignores_protection() {
+        let mut ram = Ram::new();
+        ram.write_protect((0x0300, 0xFFFF));
+        ram.load(0x0300, &[0x01, 0x02, 0x03]);
+
+        assert_eq!(ram.read(0x0300), 0x01);
+        assert_eq!(ram.read(0x0301), 0x02);
+        assert_eq!(ram.read(0x0302), 0x03);
+    }
+
+    #[test]
+    fn ram_with_size() {
+        let mut ram = Ram::with_size(2 * 1024).unwrap();
+        ram.write(0x07FF, 0xAA);
+
+        assert_eq!(ram.size(), 0x0800);
+        assert_eq!(ram.read(0x07FF), 0xAA);
+        assert_eq!(ram.read(0x0FFF), 0xAA); // mirrored
+    }
+
+    #[test]
+    fn ram_empty() {
+        assert!(Ram::with_size(0).is_err());
+        assert!(Ram::from_buffer(&mut [][..]).is_err());
+    }
+
+    #[test]
+    fn ram_load_wraps() {
+        let mut ram = Ram::with_size(0x0100).unwrap();
+        ram.load(0x02FE, &[0x01, 0x02, 0x03]);
+
+        assert_eq!(ram.read(0x00FE), 0x01);
+        assert_eq!(ram.read(0x00FF), 0x02);
+        assert_eq!(ram.read(0x0000), 0x03);
+    }
+
+    #[test]
+    fn ram_write_protect_mirrored() {
+        let mut ram = Ram::with_size(0x0800).unwrap();
+        ram.write_protect((0x0000, 0x00FF));
+        ram.write_protect((0x0FFF, 0x0FFF)); // mirror of 0x07FF
+
+        ram.write(0x0810, 0xAA);
+        ram.write(0x07FF, 0xBB);
+        ram.write(0x0900, 0xCC);
+
+        assert_eq!(ram.read(0x0010), 0x00);
+        assert_eq!(ram.read(0x07FF), 0x00);
+        assert_eq!(ram.read(0x0100), 0xCC);
+    }
+
+    #[test]
+    fn ram_from_slice() {
+        let mut buf = [0u8; 256];
+        {
+            let mut ram = Ram::from_buffer(&mut buf[..]).unwrap();
+            ram.write(0x0010, 0xAB);
+        }
+        assert_eq!(buf[0x10], 0xAB);
+    }
+
+    #[test]
+    fn ram_from_vec() {
+        let mut ram = Ram::from_buffer(vec![0x11; 16]).unwrap();
+        ram.write(0x0000, 0x22);
+
+        let data = ram.into_inner();
+        assert_eq!(data[0], 0x22);
+        assert_eq!(data[1], 0x11);
+    }
+
+    #[test]
+    fn rom_ignores_writes() {
+        let mut rom = Rom::new(&[0xEA, 0x4C]);
+        rom.write(0x0000, 0xFF);
+
+        assert_eq!(rom.read(0x0000), 0xEA);
+        assert_eq!(rom.read(0x0001), 0x4C);
+    }
+
+    #[test]
+    fn rom_mirrors() {
+        let rom = Rom::new(&[0x11, 0x22]);
+        assert_eq!(rom.read(0x0002), 0x11);
+        assert_eq!(rom.read(0x0003), 0x22);
+    }
+
+    fn banked(count: usize) -> BankedMemory {
+        let banks = (0..count)
+            .map(|_| Box::new(Ram::with_size(0x4000).unwrap()) as Box<dyn Memory + Send>)
+            .collect();
+        BankedMemory::new(banks, 0x4000, 0x4000).unwrap()
+    }
+
+    #[test]
+    fn banked_switch() {
+        let mut mem = banked(8);
+        mem.write(0x0000, 0xAA);
+        mem.write(0x4000, 5);
+        mem.write(0x0000, 0xBB);
+
+        assert_eq!(mem.active_bank(), 5);
+        assert_eq!(mem.read(0x4000), 5);
+        assert_eq!(mem.read(0x0000), 0xBB);
+
+        mem.write(0x4000, 0);
+        assert_eq!(mem.read(0x0000), 0xAA);
+    }
+
+    #[test]
+    fn banked_register_wraps() {
+        let mut mem = banked(4);
+        mem.write(0x4000, 6);
+        assert_eq!(mem.active_bank(), 2);
+    }
+
+    #[test]
+    fn banked_register_in_window() {
+        let banks = vec![
+            Box::new(Rom::new(&[0x11; 0x2000])) as Box<dyn Memory + Send>,
+            Box::new(Rom::new(&[0x22; 0x2000])) as Box<dyn Memory + Send>,
+        ];
+        let mut mem = BankedMemory::new(banks, 0x2000, 0x1FFF).unwrap();
+
+        mem.write(0x1FFF, 1);
+        assert_eq!(mem.active_bank(), 1);
+        assert_eq!(mem.read(0x1FFF), 0x22);
+    }
+
+    #[test]
+    fn banked_without_banks() {
+        assert!(BankedMemory::new(Vec::new(), 0x4000, 0x4000).is_err());
     }
 }
@@ -0,0 +1,438 @@
+use crate::image::Image;
+use crate::types::*;
+
+// The o65 relocatable object format, as produced by the xa65 assembler with `-R`.
+// Only the plain 6502 flavour is supported: 16-bit sizes and a single object per file.
+
+const MAGIC: [Byte; 5] = [0x01, 0x00, b'o', b'6', b'5'];
+
+const MODE_65816: Word = 0x8000;
+const MODE_PAGED: Word = 0x4000;
+const MODE_LONG: Word = 0x2000;
+const MODE_CHAIN: Word = 0x0400;
+const MODE_BSSZERO: Word = 0x0200;
+
+const SEG_UNDEF: Byte = 0;
+const SEG_ABS: Byte = 1;
+const SEG_TEXT: Byte = 2;
+const SEG_DATA: Byte = 3;
+const SEG_BSS: Byte = 4;
+const SEG_ZERO: Byte = 5;
+
+const RELOC_WORD: Byte = 0x80;
+const RELOC_HIGH: Byte = 0x40;
+const RELOC_LOW: Byte = 0x20;
+
+pub fn is_o65(data: &[Byte]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+// Addresses of the segments of an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub text: Word,
+    pub data: Word,
+    pub bss: Word,
+    pub zero: Word,
+}
+
+// Symbol exported by the object. The value is relative to the address the object
+// was assembled for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub segment: Byte,
+    pub value: Word,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Reloc {
+    offset: usize,
+    kind: Byte,
+    segment: Byte,
+    low: Byte,
+    undef: Word,
+}
+
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub mode: Word,
+    pub base: Layout,
+    pub text_len: Word,
+    pub data_len: Word,
+    pub bss_len: Word,
+    pub zero_len: Word,
+    pub stack: Word,
+    pub text: Vec<Byte>,
+    pub data: Vec<Byte>,
+    pub undefined: Vec<String>,
+    pub globals: Vec<Symbol>,
+    text_relocs: Vec<Reloc>,
+    data_relocs: Vec<Reloc>,
+}
+
+struct Reader<'a> {
+    data: &'a [Byte],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [Byte], String> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or("unexpected end of file")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<Byte, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<Word, String> {
+        let bytes = self.bytes(2)?;
+        Ok(Word::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.data[self.pos..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or("unterminated symbol name")?;
+        let name = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        self.pos += 1;
+        Ok(name)
+    }
+
+    // Relocation entries point at the bytes to be fixed, each one relative to the previous,
+    // starting right before the segment. Offsets of 255 only advance the position by 254.
+    fn relocs(&mut self, paged: bool) -> Result<Vec<Reloc>, String> {
+        let mut relocs = Vec::new();
+        let mut pos: isize = -1;
+
+        loop {
+            let mut offset = self.byte()?;
+            if offset == 0 {
+                break;
+            }
+            while offset == 255 {
+                pos += 254;
+                offset = self.byte()?;
+            }
+            pos += offset as isize;
+
+            let typ = self.byte()?;
+            let (kind, segment) = (typ & 0xE0, typ & 0x1F);
+            let undef = if segment == SEG_UNDEF {
+                self.word()?
+            } else {
+                0
+            };
+            let low = if kind == RELOC_HIGH && !paged {
+                self.byte()?
+            } else {
+                0
+            };
+
+            relocs.push(Reloc {
+                offset: pos as usize,
+                kind,
+                segment,
+                low,
+                undef,
+            });
+        }
+
+        Ok(relocs)
+    }
+}
+
+pub fn parse(data: &[Byte]) -> Result<Object, String> {
+    if !is_o65(data) {
+        return Err("not an o65 file".to_string());
+    }
+    let mut r = Reader {
+        data,
+        pos: MAGIC.len(),
+    };
+
+    let version = r.byte()?;
+    if version != 0 {
+        return Err(format!("unsupported version {}", version));
+    }
+    let mode = r.word()?;
+    if mode & MODE_65816 != 0 {
+        return Err("65816 objects are not supported".to_string());
+    }
+    if mode & MODE_LONG != 0 {
+        return Err("32-bit objects are not supported".to_string());
+    }
+    if mode & MODE_CHAIN != 0 {
+        return Err("chained objects are not supported".to_string());
+    }
+
+    let (text_base, text_len) = (r.word()?, r.word()?);
+    let (data_base, data_len) = (r.word()?, r.word()?);
+    let (bss_base, bss_len) = (r.word()?, r.word()?);
+    let (zero_base, zero_len) = (r.word()?, r.word()?);
+    let stack = r.word()?;
+
+    // Header options (file name, assembler, author...) are of no use for loading.
+    loop {
+        let len = r.byte()?;
+        if len == 0 {
+            break;
+        }
+        if len < 2 {
+            return Err("invalid header option".to_string());
+        }
+        r.bytes(len as usize - 1)?;
+    }
+
+    let text = r.bytes(text_len as usize)?.to_vec();
+    let data = r.bytes(data_len as usize)?.to_vec();
+
+    let count = r.word()?;
+    let undefined = (0..count).map(|_| r.name()).collect::<Result<_, _>>()?;
+
+    let paged = mode & MODE_PAGED != 0;
+    let text_relocs = r.relocs(paged)?;
+    let data_relocs = r.relocs(paged)?;
+
+    let count = r.word()?;
+    let globals = (0..count)
+        .map(|_| {
+            Ok(Symbol {
+                name: r.name()?,
+                segment: r.byte()?,
+                value: r.word()?,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(Object {
+        mode,
+        base: Layout {
+            text: text_base,
+            data: data_base,
+            bss: bss_base,
+            zero: zero_base,
+        },
+        text_len,
+        data_len,
+        bss_len,
+        zero_len,
+        stack,
+        text,
+        data,
+        undefined,
+        globals,
+        text_relocs,
+        data_relocs,
+    })
+}
+
+impl Object {
+    // Layout with the text segment at the given address, followed by data and bss.
+    // The zero page segment goes to the given address, or stays where it was assembled for.
+    pub fn layout_at(&self, base: Word, zero: Option<Byte>) -> Layout {
+        let data = base.wrapping_add(self.text_len);
+        Layout {
+            text: base,
+            data,
+            bss: data.wrapping_add(self.data_len),
+            zero: zero.map_or(self.base.zero, Word::from),
+        }
+    }
+
+    // Moves the segments to the given addresses and fixes all the references to them.
+    // The bss segment is only a part of the image when the object asks it to be zeroed.
+    pub fn relocate(&self, layout: &Layout) -> Result<Image, String> {
+        if !self.undefined.is_empty() {
+            return Err(format!("undefined symbols: {}", self.undefined.join(", ")));
+        }
+
+        let mut text = self.text.clone();
+        let mut data = self.data.clone();
+        self.apply(&mut text, &self.text_relocs, layout)
+            .map_err(|err| format!("text segment: {}", err))?;
+        self.apply(&mut data, &self.data_relocs, layout)
+            .map_err(|err| format!("data segment: {}", err))?;
+
+        let mut image = Image::default();
+        image.push(layout.text, &text)?;
+        image.push(layout.data, &data)?;
+        if self.mode & MODE_BSSZERO != 0 {
+            image.push(layout.bss, &vec![0; self.bss_len as usize])?;
+        }
+        image.segments.retain(|seg| !seg.data.is_empty());
+        Ok(image)
+    }
+
+    // Distance the given segment is moved by.
+    fn delta(&self, segment: Byte, layout: &Layout) -> Result<Word, String> {
+        let (new, old) = match segment {
+            SEG_ABS => return Ok(0),
+            SEG_TEXT => (layout.text, self.base.text),
+            SEG_DATA => (layout.data, self.base.data),
+            SEG_BSS => (layout.bss, self.base.bss),
+            SEG_ZERO => (layout.zero, self.base.zero),
+            _ => return Err(format!("invalid segment {}", segment)),
+        };
+        let delta = new.wrapping_sub(old);
+        if self.mode & MODE_PAGED != 0 && delta & 0xFF != 0 {
+            return Err("pagewise relocatable object must be moved by whole pages".to_string());
+        }
+        Ok(delta)
+    }
+
+    fn apply(&self, buf: &mut [Byte], relocs: &[Reloc], layout: &Layout) -> Result<(), String> {
+        for reloc in relocs {
+            if reloc.segment == SEG_UNDEF {
+                return Err(format!("reference to undefined symbol #{}", reloc.undef));
+            }
+            let delta = self.delta(reloc.segment, layout)?;
+            let size = if reloc.kind == RELOC_WORD { 2 } else { 1 };
+            if reloc.offset + size > buf.len() {
+                return Err(format!(
+                    "relocation at {} is out of the segment",
+                    reloc.offset
+                ));
+            }
+
+            let at = reloc.offset;
+            match reloc.kind {
+                RELOC_WORD => {
+                    let value = Word::from_le_bytes([buf[at], buf[at + 1]]).wrapping_add(delta);
+                    buf[at..at + 2].copy_from_slice(&value.to_le_bytes());
+                }
+                RELOC_HIGH => {
+                    let value = Word::from_le_bytes([reloc.low, buf[at]]).wrapping_add(delta);
+                    buf[at] = (value >> 8) as Byte;
+                }
+                RELOC_LOW => {
+                    buf[at] = buf[at].wrapping_add(delta as Byte);
+                }
+                kind => return Err(format!("unsupported relocation type 0x{:02X}", kind)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod o65_test {
+    use super::*;
+
+    // Assembled for text at 0x1000 and data at 0x2000:
+    //   start: LDA value ; JMP start ; LDA #>value ; LDA #<value
+    //   value: .byte $42
+    fn object(undefined: &[&str]) -> Vec<Byte> {
+        let mut obj = vec![0x01, 0x00, b'o', b'6', b'5', 0x00];
+        obj.extend_from_slice(&[0x00, 0x00]); // mode
+        obj.extend_from_slice(&[0x00, 0x10, 0x0A, 0x00]); // text
+        obj.extend_from_slice(&[0x00, 0x20, 0x01, 0x00]); // data
+        obj.extend_from_slice(&[0x00, 0x30, 0x04, 0x00]); // bss
+        obj.extend_from_slice(&[0x10, 0x00, 0x00, 0x00]); // zero page
+        obj.extend_from_slice(&[0x00, 0x00]); // stack
+        obj.extend_from_slice(&[0x05, 0x00, b'h', b'i', 0x00]); // file name option
+        obj.push(0x00);
+
+        obj.extend_from_slice(&[0xAD, 0x00, 0x20, 0x4C, 0x00, 0x10, 0xA9, 0x20, 0xA9, 0x00]);
+        obj.push(0x42);
+
+        obj.extend_from_slice(&(undefined.len() as Word).to_le_bytes());
+        for name in undefined {
+            obj.extend_from_slice(name.as_bytes());
+            obj.push(0x00);
+        }
+
+        obj.extend_from_slice(&[0x02, RELOC_WORD | SEG_DATA]);
+        obj.extend_from_slice(&[0x03, RELOC_WORD | SEG_TEXT]);
+        obj.extend_from_slice(&[0x03, RELOC_HIGH | SEG_DATA, 0x00]);
+        obj.extend_from_slice(&[0x02, RELOC_LOW | SEG_DATA]);
+        obj.push(0x00);
+        obj.push(0x00); // no data relocations
+
+        obj.extend_from_slice(&[0x01, 0x00, b's', b't', b'a', b'r', b't', 0x00, SEG_TEXT]);
+        obj.extend_from_slice(&[0x00, 0x10]);
+        obj
+    }
+
+    #[test]
+    fn parse_object() {
+        let obj = parse(&object(&[])).unwrap();
+        assert_eq!(obj.base.text, 0x1000);
+        assert_eq!(obj.base.data, 0x2000);
+        assert_eq!(obj.bss_len, 4);
+        assert_eq!(obj.text.len(), 10);
+        assert_eq!(obj.data, vec![0x42]);
+        assert_eq!(obj.text_relocs.len(), 4);
+        assert_eq!(
+            obj.globals,
+            vec![Symbol {
+                name: "start".to_string(),
+                segment: SEG_TEXT,
+                value: 0x1000
+            }]
+        );
+    }
+
+    #[test]
+    fn relocate() {
+        let obj = parse(&object(&[])).unwrap();
+        let layout = obj.layout_at(0x4000, None);
+        assert_eq!(layout.data, 0x400A);
+        assert_eq!(layout.bss, 0x400B);
+
+        let image = obj.relocate(&layout).unwrap();
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].addr, 0x4000);
+        assert_eq!(
+            image.segments[0].data,
+            vec![0xAD, 0x0A, 0x40, 0x4C, 0x00, 0x40, 0xA9, 0x40, 0xA9, 0x0A, 0x42]
+        );
+    }
+
+    #[test]
+    fn relocate_zero_page() {
+        // the operand of LDA #<value becomes an address in the zero page, assembled for 0x10
+        let mut obj = parse(&object(&[])).unwrap();
+        obj.text[9] = 0x12;
+        obj.text_relocs[3].segment = SEG_ZERO;
+
+        let layout = obj.layout_at(0x4000, None);
+        assert_eq!(layout.zero, 0x10);
+        assert_eq!(obj.relocate(&layout).unwrap().segments[0].data[9], 0x12);
+
+        let layout = obj.layout_at(0x4000, Some(0x80));
+        assert_eq!(layout.zero, 0x80);
+        assert_eq!(obj.relocate(&layout).unwrap().segments[0].data[9], 0x82);
+    }
+
+    #[test]
+    fn relocate_in_place() {
+        let obj = parse(&object(&[])).unwrap();
+        let image = obj.relocate(&obj.base).unwrap();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].data, obj.text);
+        assert_eq!(image.segments[1].addr, 0x2000);
+    }
+
+    #[test]
+    fn undefined_symbols() {
+        let obj = parse(&object(&["putc", "getc"])).unwrap();
+        let err = obj.relocate(&obj.layout_at(0x4000, None)).unwrap_err();
+        assert_eq!(err, "undefined symbols: putc, getc");
+    }
+
+    #[test]
+    fn truncated() {
+        let data = object(&[]);
+        assert_eq!(
+            parse(&data[..30]).unwrap_err(),
+            "unexpected end of file".to_string()
+        );
+    }
+}
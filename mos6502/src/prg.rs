@@ -0,0 +1,34 @@
+use crate::image::Image;
+use crate::types::*;
+
+// Parses a Commodore PRG file: the load address in little endian followed by the data.
+pub fn parse(data: &[Byte]) -> Result<Image, String> {
+    if data.len() < 2 {
+        return Err("missing load address".to_string());
+    }
+    let addr = Word::from_le_bytes([data[0], data[1]]);
+    Image::binary(addr, &data[2..])
+}
+
+#[cfg(test)]
+mod prg_test {
+    use super::*;
+
+    #[test]
+    fn parse_prg() {
+        let image = parse(&[0x01, 0x08, 0x0B, 0x08, 0x0A]).unwrap();
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].addr, 0x0801);
+        assert_eq!(image.segments[0].data, vec![0x0B, 0x08, 0x0A]);
+    }
+
+    #[test]
+    fn missing_address() {
+        assert!(parse(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(parse(&[0xFF, 0xFF, 0xEA, 0xEA]).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use crate::mem::{Device, Memory};
+use crate::types::*;
+
+const LATCH: Byte = 0b1000_0000; // selects the register and sets its lower 4 bits
+const LATCH_REGISTER: Byte = 0b0111_0000;
+const LATCH_VOLUME: Byte = 0b0001_0000; // attenuation instead of the tone or noise control
+
+const NOISE_WHITE: Byte = 0b0000_0100; // white noise instead of periodic
+const NOISE_RATE: Byte = 0b0000_0011; // 3 is the period of tone channel 2
+
+const NOISE_CHANNEL: usize = 3;
+const LFSR_RESET: u16 = 0x4000; // the shift register is 15 bits long
+
+// Amplitude of one channel for each attenuation step of 2 dB. The last one is silence.
+const VOLUMES: [i16; 16] = [
+    8191, 6506, 5168, 4105, 3261, 2590, 2057, 1634, 1298, 1031, 819, 651, 517, 411, 326, 0,
+];
+
+// TI SN76489 programmable sound generator: three square wave channels and one noise channel,
+// each with its own attenuation. All the registers are set through a single write-only port.
+// The chip is emulated in step with the CPU and its output is sampled at the given rate,
+// averaging the levels between the samples. Only integer arithmetic is used, so the same
+// program always produces exactly the same samples.
+pub struct Psg {
+    cpu_hz: u64,
+    clock_hz: u64,
+    sample_rate: u64,
+
+    latched: Byte, // register the data bytes go to, channel in bits 6-5 and volume in bit 4
+    periods: [Word; 4],
+    counters: [Word; 4],
+    outputs: [bool; 4],
+    attenuation: [Byte; 4],
+    noise: Byte,
+    lfsr: u16,
+
+    clock_phase: u64,
+    sample_phase: u64,
+    level_sum: i64,
+    level_count: i64,
+    samples: Vec<i16>,
+}
+
+impl Psg {
+    // The frequencies are in MHz, like the one of the CPU, and the sample rate is in Hz.
+    pub fn new(cpu_frequency: f32, clock: f32, sample_rate: u32) -> Psg {
+        Psg {
+            cpu_hz: (cpu_frequency as f64 * 1_000_000.0).round() as u64,
+            clock_hz: (clock as f64 * 1_000_000.0).round() as u64,
+            sample_rate: sample_rate as u64,
+            latched: 0,
+            periods: [0; 4],
+            counters: [0; 4],
+            outputs: [true; 4],
+            attenuation: [0x0F; 4],
+            noise: 0,
+            lfsr: LFSR_RESET,
+            clock_phase: 0,
+            sample_phase: 0,
+            level_sum: 0,
+            level_count: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    // Signed 16-bit mono samples generated so far.
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    // Hands over the samples generated since the last call, so they do not pile up.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    fn write_register(&mut self, data: Byte) {
+        if data & LATCH != 0 {
+            self.latched = data & LATCH_REGISTER;
+        }
+
+        let channel = (self.latched >> 5) as usize;
+        match (channel, data & LATCH != 0) {
+            _ if self.latched & LATCH_VOLUME != 0 => {
+                self.attenuation[channel] = data & 0x0F;
+            }
+            (NOISE_CHANNEL, _) => {
+                self.noise = data & 0x07;
+                self.lfsr = LFSR_RESET;
+            }
+            (_, true) => {
+                self.periods[channel] = (self.periods[channel] & 0x3F0) | (data & 0x0F) as Word;
+            }
+            (_, false) => {
+                self.periods[channel] =
+                    (self.periods[channel] & 0x00F) | (((data & 0x3F) as Word) << 4);
+            }
+        }
+    }
+
+    fn noise_period(&self) -> Word {
+        match self.noise & NOISE_RATE {
+            3 => self.periods[2],
+            rate => 0x10 << rate,
+        }
+    }
+
+    // Called at 1/16 of the chip clock.
+    fn step(&mut self) {
+        for channel in 0..3 {
+            self.counters[channel] = self.counters[channel].saturating_sub(1);
+            if self.counters[channel] == 0 {
+                let period = self.periods[channel];
+                self.counters[channel] = period;
+                // periods of 0 and 1 are too short to be heard, so the output stays high
+                self.outputs[channel] = period <= 1 || !self.outputs[channel];
+            }
+        }
+
+        self.counters[NOISE_CHANNEL] = self.counters[NOISE_CHANNEL].saturating_sub(1);
+        if self.counters[NOISE_CHANNEL] == 0 {
+            self.counters[NOISE_CHANNEL] = self.noise_period().max(1);
+            self.outputs[NOISE_CHANNEL] = !self.outputs[NOISE_CHANNEL];
+            if self.outputs[NOISE_CHANNEL] {
+                let feedback = if self.noise & NOISE_WHITE != 0 {
+                    (self.lfsr ^ (self.lfsr >> 1)) & 1
+                } else {
+                    self.lfsr & 1
+                };
+                self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+            }
+        }
+    }
+
+    fn level(&self) -> i64 {
+        let mut level = 0;
+        for channel in 0..4 {
+            let high = match channel {
+                NOISE_CHANNEL => self.lfsr & 1 != 0,
+                _ => self.outputs[channel],
+            };
+            let volume = VOLUMES[self.attenuation[channel] as usize] as i64;
+            level += if high { volume } else { -volume };
+        }
+        level
+    }
+
+    fn cycle(&mut self) {
+        self.clock_phase += self.clock_hz;
+        while self.clock_phase >= self.cpu_hz * 16 {
+            self.clock_phase -= self.cpu_hz * 16;
+            self.step();
+        }
+
+        self.level_sum += self.level();
+        self.level_count += 1;
+        self.sample_phase += self.sample_rate;
+        while self.sample_phase >= self.cpu_hz {
+            self.sample_phase -= self.cpu_hz;
+            let sample = self.level_sum / self.level_count.max(1);
+            self.samples.push(sample as i16);
+            self.level_sum = 0;
+            self.level_count = 0;
+        }
+    }
+}
+
+impl Memory for Psg {
+    fn read(&self, _addr: Word) -> Byte {
+        0
+    }
+
+    fn write(&mut self, _addr: Word, data: Byte) {
+        self.write_register(data);
+    }
+}
+
+impl Device for Psg {
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.cycle();
+        }
+    }
+}
+
+#[cfg(test)]
+mod psg_test {
+    use super::*;
+
+    // With the chip clocked at 16 times the CPU, it steps once per cycle,
+    // and the sample rate of one sample per cycle shows every step.
+    fn psg() -> Psg {
+        Psg::new(1.0, 16.0, 1_000_000)
+    }
+
+    fn tone(psg: &mut Psg, channel: Byte, period: Word, attenuation: Byte) {
+        psg.write(0, LATCH | (channel << 5) | (period & 0x0F) as Byte);
+        psg.write(0, (period >> 4) as Byte);
+        psg.write(0, LATCH | (channel << 5) | LATCH_VOLUME | attenuation);
+    }
+
+    #[test]
+    fn silent_on_reset() {
+        let mut psg = psg();
+        psg.tick(100);
+        assert_eq!(psg.samples().len(), 100);
+        assert!(psg.samples().iter().all(|&s| s == 0));
+
+        assert_eq!(psg.take_samples().len(), 100);
+        assert!(psg.samples().is_empty());
+    }
+
+    #[test]
+    fn registers() {
+        let mut psg = psg();
+        tone(&mut psg, 1, 0x3A5, 0x03);
+        assert_eq!(psg.periods[1], 0x3A5);
+        assert_eq!(psg.attenuation[1], 0x03);
+
+        // data bytes go to the latched register, volume and noise take 4 bits
+        psg.write(0, LATCH | 0x20 | 0x05);
+        psg.write(0, 0x12);
+        assert_eq!(psg.periods[1], 0x125);
+        psg.write(0, LATCH | 0x30 | 0x01);
+        psg.write(0, 0x0E);
+        assert_eq!(psg.attenuation[1], 0x0E);
+        psg.write(0, LATCH | 0x60 | 0x05);
+        assert_eq!(psg.noise, 0x05);
+    }
+
+    #[test]
+    fn square_wave() {
+        let mut psg = psg();
+        tone(&mut psg, 0, 4, 0x00);
+        psg.tick(16);
+
+        let volume = VOLUMES[0];
+        let expected: Vec<i16> = (0..16)
+            .map(|i| if (i / 4) % 2 == 0 { -volume } else { volume })
+            .collect();
+        assert_eq!(psg.samples(), expected);
+    }
+
+    #[test]
+    fn averaging() {
+        // 4 CPU cycles per sample, while the tone changes every 2 steps
+        let mut psg = Psg::new(1.0, 16.0, 250_000);
+        tone(&mut psg, 2, 2, 0x00);
+        psg.tick(16);
+        assert_eq!(psg.samples(), [0; 4]);
+
+        // 8 cycles per step, 2 steps per sample, and a period of 1 keeps the output high
+        let mut psg = Psg::new(1.0, 2.0, 62_500);
+        tone(&mut psg, 2, 1, 0x01);
+        psg.tick(32);
+        assert_eq!(psg.samples(), [VOLUMES[1], VOLUMES[1]]);
+    }
+
+    #[test]
+    fn noise() {
+        let mut psg = psg();
+        psg.write(0, LATCH | 0x60 | NOISE_WHITE); // fastest rate, period 16
+        psg.write(0, LATCH | 0x70);
+        psg.tick(16 * 2 * 100);
+
+        let samples = psg.samples().to_vec();
+        assert!(samples.contains(&VOLUMES[0]) && samples.contains(&-VOLUMES[0]));
+        // the level can only change on the shifts, every 32 steps
+        assert!(samples[16..]
+            .chunks(32)
+            .all(|c| c.iter().all(|&s| s == c[0])));
+
+        // writing the control register resets the shift register, so the noise starts over
+        psg.write(0, LATCH | 0x60 | NOISE_WHITE);
+        psg.tick(16 * 2 * 100);
+        assert_eq!(psg.samples()[samples.len()..], samples);
+    }
+
+    #[test]
+    fn periodic_noise() {
+        let mut psg = psg();
+        psg.write(0, LATCH | 0x60); // periodic, period 16
+        psg.write(0, LATCH | 0x70);
+        psg.tick(16 * 2 * 15);
+
+        // one bit in 15 is set, and it takes 15 shifts to come around
+        let highs = psg.samples().iter().filter(|&&s| s > 0).count();
+        assert_eq!(highs, 32);
+    }
+}
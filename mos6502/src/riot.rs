@@ -0,0 +1,385 @@
+use crate::mem::{Device, Memory};
+use crate::types::*;
+use crate::via::{Peripheral, Port};
+use std::cell::Cell;
+
+const RAM_SIZE: usize = 128;
+
+// The chip decodes 8 address lines. A7 is the RAM select line, so the lower half
+// of the range is RAM and the upper half is I/O. Both are mirrored.
+const SELECT_IO: Word = 0b1000_0000;
+const SELECT_TIMER: Word = 0b0000_0100; // A2, timer and edge control instead of the ports
+const WRITE_TIMER: Word = 0b0001_0000; // A4, writing the timer instead of the edge control
+const TIMER_IRQ: Word = 0b0000_1000; // A3, enables the timer interrupt when the timer is accessed
+
+const REG_ORA: Word = 0x00;
+const REG_DDRA: Word = 0x01;
+const REG_ORB: Word = 0x02;
+const REG_DDRB: Word = 0x03;
+
+const FLAG_TIMER: Byte = 0b1000_0000;
+const FLAG_PA7: Byte = 0b0100_0000;
+
+const PRESCALERS: [u16; 4] = [1, 8, 64, 1024];
+
+// MOS 6532 RAM-I/O-Timer: 128 bytes of RAM, two 8-bit ports with data direction registers,
+// an interval timer with a prescaler of 1, 8, 64 or 1024 cycles, and an edge detector
+// on PA7. Both the timer and PA7 can raise an interrupt. Reading the port may have side
+// effects on the peripheral, so an input PA7 is only watched once the program has selected
+// an edge for the detector.
+pub struct Riot {
+    ram: [Byte; RAM_SIZE],
+    a: Port,
+    b: Port,
+
+    flags: Cell<Byte>,
+    timer: Cell<Byte>,
+    prescaler: u16,
+    divider: u16,  // cycles until the next decrement of the timer
+    expired: bool, // the timer has passed zero and counts every cycle
+    timer_irq: Cell<bool>,
+
+    pa7: bool,
+    pa7_rising: bool,
+    pa7_irq: bool,
+    pa7_armed: bool, // the edge control has been written
+}
+
+impl Default for Riot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Riot {
+    pub fn new() -> Riot {
+        Riot {
+            ram: [0; RAM_SIZE],
+            a: Port::new(),
+            b: Port::new(),
+            flags: Cell::new(0),
+            timer: Cell::new(0),
+            prescaler: 1,
+            divider: 1,
+            expired: false,
+            timer_irq: Cell::new(false),
+            pa7: true,
+            pa7_rising: false,
+            pa7_irq: false,
+            pa7_armed: false,
+        }
+    }
+
+    pub fn attach_a(&mut self, peripheral: Box<dyn Peripheral + Send>) {
+        self.a.attach(peripheral);
+        self.pa7 = self.a.read_pins() & 0x80 != 0;
+    }
+
+    pub fn attach_b(&mut self, peripheral: Box<dyn Peripheral + Send>) {
+        self.b.attach(peripheral);
+    }
+
+    // Levels the RIOT drives port A and B to, with the inputs pulled high.
+    pub fn port_a(&self) -> Byte {
+        self.a.pins.get()
+    }
+
+    pub fn port_b(&self) -> Byte {
+        self.b.pins.get()
+    }
+
+    fn update_pins(&self) {
+        for port in [&self.a, &self.b] {
+            port.drive((port.or & port.ddr) | !port.ddr);
+        }
+    }
+
+    fn step(&mut self) {
+        self.divider -= 1;
+        if self.divider == 0 {
+            let timer = self.timer.get().wrapping_sub(1);
+            self.timer.set(timer);
+            if timer == 0xFF && !self.expired {
+                self.expired = true;
+                self.flags.set(self.flags.get() | FLAG_TIMER);
+            }
+            self.divider = if self.expired { 1 } else { self.prescaler };
+        }
+
+        let pa7 = if self.a.ddr & 0x80 != 0 {
+            self.a.or & 0x80 != 0
+        } else if self.pa7_armed {
+            self.a.read_pins() & 0x80 != 0
+        } else {
+            self.pa7
+        };
+        if pa7 != self.pa7 {
+            self.pa7 = pa7;
+            if pa7 == self.pa7_rising {
+                self.flags.set(self.flags.get() | FLAG_PA7);
+            }
+        }
+
+        self.a.tick(1);
+        self.b.tick(1);
+    }
+}
+
+impl Memory for Riot {
+    fn read(&self, addr: Word) -> Byte {
+        if addr & SELECT_IO == 0 {
+            return self.ram[addr as usize % RAM_SIZE];
+        }
+
+        if addr & SELECT_TIMER == 0 {
+            return match addr & 0x03 {
+                REG_ORA => self.a.read_pins(),
+                REG_DDRA => self.a.ddr,
+                REG_ORB => self.b.read_pins(),
+                REG_DDRB => self.b.ddr,
+                _ => unreachable!(),
+            };
+        }
+
+        // Reading the timer clears its flag, reading the flags clears the PA7 one.
+        if addr & 0x01 == 0 {
+            self.timer_irq.set(addr & TIMER_IRQ != 0);
+            self.flags.set(self.flags.get() & !FLAG_TIMER);
+            self.timer.get()
+        } else {
+            let flags = self.flags.get();
+            self.flags.set(flags & !FLAG_PA7);
+            flags
+        }
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        if addr & SELECT_IO == 0 {
+            self.ram[addr as usize % RAM_SIZE] = data;
+            return;
+        }
+
+        if addr & SELECT_TIMER == 0 {
+            match addr & 0x03 {
+                REG_ORA => self.a.or = data,
+                REG_DDRA => self.a.ddr = data,
+                REG_ORB => self.b.or = data,
+                REG_DDRB => self.b.ddr = data,
+                _ => unreachable!(),
+            }
+            self.update_pins();
+        } else if addr & WRITE_TIMER != 0 {
+            self.prescaler = PRESCALERS[addr as usize & 0x03];
+            self.divider = self.prescaler;
+            self.expired = false;
+            self.timer.set(data);
+            self.timer_irq.set(addr & TIMER_IRQ != 0);
+            self.flags.set(self.flags.get() & !FLAG_TIMER);
+        } else {
+            self.pa7_rising = addr & 0x01 != 0;
+            self.pa7_irq = addr & 0x02 != 0;
+            if !self.pa7_armed && self.a.ddr & 0x80 == 0 {
+                self.pa7 = self.a.read_pins() & 0x80 != 0;
+            }
+            self.pa7_armed = true;
+        }
+    }
+}
+
+impl Device for Riot {
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        let flags = self.flags.get();
+        (flags & FLAG_TIMER != 0 && self.timer_irq.get()) || (flags & FLAG_PA7 != 0 && self.pa7_irq)
+    }
+}
+
+#[cfg(test)]
+mod riot_test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const IO: Word = 0x80;
+    const TIMER: Word = IO | SELECT_TIMER;
+    const FLAGS: Word = TIMER | 0x01;
+
+    #[derive(Clone)]
+    struct Pins(Arc<Mutex<Byte>>);
+
+    impl Peripheral for Pins {
+        fn read_port(&mut self) -> Byte {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn ram_mirroring() {
+        let mut riot = Riot::new();
+        riot.write(0x05, 0x42);
+        assert_eq!(riot.read(0x05), 0x42);
+        assert_eq!(riot.read(0x105), 0x42);
+
+        riot.write(0x17F, 0x24);
+        assert_eq!(riot.read(0x7F), 0x24);
+
+        // the I/O half does not touch the RAM
+        riot.write(IO | 0x05, 0xFF);
+        assert_eq!(riot.read(0x05), 0x42);
+    }
+
+    #[test]
+    fn ports() {
+        let pins = Pins(Arc::new(Mutex::new(0b1010_1010)));
+        let mut riot = Riot::new();
+        riot.attach_a(Box::new(pins.clone()));
+
+        riot.write(IO | REG_DDRA, 0x0F);
+        riot.write(IO | REG_ORA, 0x05);
+        assert_eq!(riot.port_a(), 0xF5);
+        assert_eq!(riot.read(IO | REG_ORA), 0b1010_0101);
+        assert_eq!(riot.read(IO | REG_DDRA), 0x0F);
+
+        // registers are mirrored on the upper address lines
+        riot.write(IO | 0x60 | REG_DDRB, 0xFF);
+        riot.write(IO | 0x40 | REG_ORB, 0x3C);
+        assert_eq!(riot.port_b(), 0x3C);
+        assert_eq!(riot.read(IO | REG_ORB), 0x3C);
+    }
+
+    #[test]
+    fn timer_prescalers() {
+        for (i, &prescaler) in PRESCALERS.iter().enumerate() {
+            let mut riot = Riot::new();
+            riot.write(TIMER | WRITE_TIMER | i as Word, 3);
+
+            riot.tick(prescaler as u64 - 1);
+            assert_eq!(riot.read(TIMER), 3, "{}T", prescaler);
+            riot.tick(1);
+            assert_eq!(riot.read(TIMER), 2, "{}T", prescaler);
+
+            riot.tick(2 * prescaler as u64);
+            assert_eq!(riot.read(TIMER), 0, "{}T", prescaler);
+            assert_eq!(riot.read(FLAGS) & FLAG_TIMER, 0, "{}T", prescaler);
+
+            riot.tick(prescaler as u64);
+            assert_eq!(riot.read(FLAGS) & FLAG_TIMER, FLAG_TIMER, "{}T", prescaler);
+            assert_eq!(riot.read(TIMER), 0xFF, "{}T", prescaler);
+        }
+    }
+
+    #[test]
+    fn timer_after_expiry() {
+        let mut riot = Riot::new();
+        riot.write(TIMER | WRITE_TIMER | 0x03, 0);
+        riot.tick(1024);
+        assert_eq!(riot.read(FLAGS), FLAG_TIMER);
+
+        // after passing zero the timer counts every cycle
+        riot.tick(0x10);
+        assert_eq!(riot.read(TIMER), 0xEF);
+
+        // reading the timer cleared the flag, and it does not come back
+        assert_eq!(riot.read(FLAGS), 0);
+        riot.tick(0x200);
+        assert_eq!(riot.read(FLAGS), 0);
+    }
+
+    #[test]
+    fn timer_interrupt() {
+        let mut riot = Riot::new();
+        riot.write(TIMER | WRITE_TIMER, 1);
+        riot.tick(2);
+        assert_eq!(riot.read(FLAGS), FLAG_TIMER);
+        assert!(!riot.irq());
+
+        riot.write(TIMER | WRITE_TIMER | TIMER_IRQ, 1);
+        riot.tick(2);
+        assert!(riot.irq());
+
+        // A3 of the read enables or disables the interrupt as well
+        riot.read(TIMER);
+        assert!(!riot.irq());
+        riot.write(TIMER | WRITE_TIMER, 0);
+        riot.read(TIMER | TIMER_IRQ);
+        riot.tick(1);
+        assert!(riot.irq());
+    }
+
+    #[test]
+    fn pa7_edge() {
+        let pins = Pins(Arc::new(Mutex::new(0xFF)));
+        let mut riot = Riot::new();
+        riot.attach_a(Box::new(pins.clone()));
+
+        // negative edge, interrupt enabled
+        riot.write(TIMER | 0x02, 0);
+        riot.tick(1);
+        assert!(!riot.irq());
+
+        *pins.0.lock().unwrap() = 0x7F;
+        riot.tick(1);
+        assert!(riot.irq());
+        assert_eq!(riot.read(FLAGS) & FLAG_PA7, FLAG_PA7);
+        assert_eq!(riot.read(FLAGS) & FLAG_PA7, 0);
+        assert!(!riot.irq());
+
+        // the rising edge is ignored unless selected
+        *pins.0.lock().unwrap() = 0xFF;
+        riot.tick(1);
+        assert!(!riot.irq());
+
+        riot.write(TIMER | 0x01, 0); // positive edge, interrupt disabled
+        *pins.0.lock().unwrap() = 0x7F;
+        riot.tick(1);
+        *pins.0.lock().unwrap() = 0xFF;
+        riot.tick(1);
+        assert!(!riot.irq());
+        assert_eq!(riot.read(FLAGS) & FLAG_PA7, FLAG_PA7);
+    }
+
+    #[derive(Clone, Default)]
+    struct Reads(Arc<Mutex<usize>>);
+
+    impl Peripheral for Reads {
+        fn read_port(&mut self) -> Byte {
+            *self.0.lock().unwrap() += 1;
+            0xFF
+        }
+    }
+
+    #[test]
+    fn pa7_sampling() {
+        let reads = Reads::default();
+        let mut riot = Riot::new();
+        riot.attach_a(Box::new(reads.clone()));
+        *reads.0.lock().unwrap() = 0;
+
+        // the port is not read until the program selects an edge
+        riot.tick(100);
+        assert_eq!(*reads.0.lock().unwrap(), 0);
+
+        // nor while PA7 is an output
+        riot.write(IO | REG_DDRA, 0x80);
+        riot.write(TIMER, 0);
+        riot.tick(100);
+        assert_eq!(*reads.0.lock().unwrap(), 0);
+
+        riot.write(IO | REG_DDRA, 0x00);
+        riot.tick(100);
+        assert_eq!(*reads.0.lock().unwrap(), 100);
+    }
+
+    #[test]
+    fn pa7_output() {
+        let mut riot = Riot::new();
+        riot.write(TIMER | 0x02, 0);
+        riot.write(IO | REG_DDRA, 0x80);
+        riot.tick(1);
+        assert!(riot.irq());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::image::{decode_hex, Image};
+use crate::types::*;
+
+// Parses a Motorola S-record file. Each record looks like `STCCAA..AADD..DDSS`, where T is the
+// record type, CC is the number of the remaining bytes, AA..AA is a 2, 3 or 4 byte address and
+// SS is the one's complement of the sum of all the bytes after the type. S1-S3 records carry the
+// data, S5-S6 hold the number of data records seen so far and S7-S9 set the entry point.
+pub fn parse(text: &str) -> Result<Image, String> {
+    let mut image = Image::default();
+    let mut data_records: u32 = 0;
+    let mut terminated = false;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let err = |msg: &str| format!("line {}: {}", n + 1, msg);
+        if terminated {
+            return Err(err("record after the termination record"));
+        }
+
+        let Some(rest) = line.strip_prefix('S') else {
+            return Err(err("record must start with 'S'"));
+        };
+        let (kind, hex) = match (rest.get(..1), rest.get(1..)) {
+            (Some(kind), Some(hex)) => (kind, hex),
+            _ => return Err(err("unknown record type")),
+        };
+        let addr_len = match kind {
+            "0" | "1" | "5" | "9" => 2,
+            "2" | "6" | "8" => 3,
+            "3" | "7" => 4,
+            _ => return Err(err("unknown record type")),
+        };
+
+        let bytes = decode_hex(hex).map_err(|e| err(&e))?;
+        if bytes.len() < addr_len + 2 || bytes.len() != bytes[0] as usize + 1 {
+            return Err(err("invalid record length"));
+        }
+
+        let checksum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        if checksum != 0xFF {
+            return Err(err("checksum mismatch"));
+        }
+
+        let addr = bytes[1..=addr_len]
+            .iter()
+            .fold(0u32, |addr, &b| (addr << 8) | b as u32);
+        let data = &bytes[addr_len + 1..bytes.len() - 1];
+
+        match kind {
+            "0" => {} // header
+            "1" | "2" | "3" => {
+                let addr = Word::try_from(addr)
+                    .map_err(|_| err("address is out of the 64K address space"))?;
+                image.push(addr, data).map_err(|e| err(&e))?;
+                data_records += 1;
+            }
+            "5" | "6" => {
+                if addr != data_records {
+                    return Err(err("record count mismatch"));
+                }
+            }
+            _ => {
+                let entry =
+                    Word::try_from(addr).map_err(|_| err("start address is out of range"))?;
+                image.entry = Some(entry);
+                terminated = true;
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod srec_test {
+    use super::*;
+
+    #[test]
+    fn parse_data() {
+        let image = parse(
+            "S008000048454C4C4F83\n\
+             S1060300A90A8DB6\n\
+             S10503030002F2\n\
+             S20600FFFC0003FB\n\
+             S5030003F9\n\
+             S9030300F9\n",
+        )
+        .unwrap();
+
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].addr, 0x0300);
+        assert_eq!(image.segments[0].data, vec![0xA9, 0x0A, 0x8D, 0x00, 0x02]);
+        assert_eq!(image.segments[1].addr, 0xFFFC);
+        assert_eq!(image.segments[1].data, vec![0x00, 0x03]);
+        assert_eq!(image.entry, Some(0x0300));
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let err = parse("S1060300A90A8DB7\n").unwrap_err();
+        assert_eq!(err, "line 1: checksum mismatch");
+    }
+
+    #[test]
+    fn record_count_mismatch() {
+        let err = parse("S1060300A90A8DB6\nS5030003F9\n").unwrap_err();
+        assert_eq!(err, "line 2: record count mismatch");
+    }
+
+    #[test]
+    fn address_out_of_range() {
+        let err = parse("S205010000EA0F\n").unwrap_err();
+        assert_eq!(err, "line 1: address is out of the 64K address space");
+    }
+
+    #[test]
+    fn unknown_record_type() {
+        assert_eq!(parse("S\n").unwrap_err(), "line 1: unknown record type");
+        assert_eq!(
+            parse("Sé0300\n").unwrap_err(),
+            "line 1: unknown record type"
+        );
+        assert_eq!(
+            parse("S4030000FC\n").unwrap_err(),
+            "line 1: unknown record type"
+        );
+    }
+}
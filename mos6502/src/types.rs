@@ -1,3 +1,4 @@
 pub type Byte = u8;
 pub type Word = u16;
 pub type Flag = u8;
+pub type MemRange = (Word, Word);
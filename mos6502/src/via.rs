@@ -0,0 +1,951 @@
+use crate::mem::{Device, Memory};
+use crate::types::*;
+use std::cell::{Cell, RefCell};
+
+const REG_ORB: Word = 0x00;
+const REG_ORA: Word = 0x01;
+const REG_DDRB: Word = 0x02;
+const REG_DDRA: Word = 0x03;
+const REG_T1CL: Word = 0x04;
+const REG_T1CH: Word = 0x05;
+const REG_T1LL: Word = 0x06;
+const REG_T1LH: Word = 0x07;
+const REG_T2CL: Word = 0x08;
+const REG_T2CH: Word = 0x09;
+const REG_SR: Word = 0x0A;
+const REG_ACR: Word = 0x0B;
+const REG_PCR: Word = 0x0C;
+const REG_IFR: Word = 0x0D;
+const REG_IER: Word = 0x0E;
+const REG_ORA_NH: Word = 0x0F; // port A without handshake
+
+const IRQ_CA2: Byte = 0b0000_0001;
+const IRQ_CA1: Byte = 0b0000_0010;
+const IRQ_SR: Byte = 0b0000_0100;
+const IRQ_CB2: Byte = 0b0000_1000;
+const IRQ_CB1: Byte = 0b0001_0000;
+const IRQ_T2: Byte = 0b0010_0000;
+const IRQ_T1: Byte = 0b0100_0000;
+const IRQ_ANY: Byte = 0b1000_0000;
+
+const ACR_PA_LATCH: Byte = 0b0000_0001;
+const ACR_PB_LATCH: Byte = 0b0000_0010;
+const ACR_SR_MODE: Byte = 0b0001_1100;
+const ACR_T2_PULSES: Byte = 0b0010_0000;
+const ACR_T1_FREE_RUN: Byte = 0b0100_0000;
+const ACR_T1_PB7: Byte = 0b1000_0000;
+
+const SR_DISABLED: Byte = 0b000;
+const SR_IN_T2: Byte = 0b001;
+const SR_IN_PHI2: Byte = 0b010;
+const SR_IN_EXT: Byte = 0b011;
+const SR_OUT_FREE: Byte = 0b100;
+const SR_OUT_T2: Byte = 0b101;
+const SR_OUT_PHI2: Byte = 0b110;
+const SR_OUT_EXT: Byte = 0b111;
+
+// Modes of the CA2/CB2 lines, as set in the PCR.
+const C2_INPUT_NEG: Byte = 0b000;
+const C2_INDEPENDENT_NEG: Byte = 0b001;
+const C2_INPUT_POS: Byte = 0b010;
+const C2_INDEPENDENT_POS: Byte = 0b011;
+const C2_HANDSHAKE: Byte = 0b100;
+const C2_PULSE: Byte = 0b101;
+const C2_LOW: Byte = 0b110;
+const C2_HIGH: Byte = 0b111;
+
+// Anything connected to a port of the VIA: an LCD, a keypad, an SD card or a row of LEDs.
+// The VIA calls it back whenever it changes the levels of the lines it drives, and asks it
+// for the levels of the lines it reads. Unconnected lines are pulled high.
+pub trait Peripheral {
+    // The port lines have changed. Lines configured as inputs are reported as high.
+    fn write_port(&mut self, _pins: Byte) {}
+
+    // Levels the peripheral puts on the port lines. Only the lines configured as inputs matter.
+    fn read_port(&mut self) -> Byte {
+        0xFF
+    }
+
+    // Levels the peripheral puts on the control lines. They are sampled every cycle,
+    // and the edges raise interrupts or clock the shift register.
+    fn c1(&self) -> bool {
+        true
+    }
+
+    fn c2(&self) -> bool {
+        true
+    }
+
+    // CB1 is driven by the VIA when it is the clock of the shift register.
+    fn write_c1(&mut self, _level: bool) {}
+
+    // C2 is driven by the VIA in the output modes and by the shift register.
+    fn write_c2(&mut self, _level: bool) {}
+
+    fn tick(&mut self, _cycles: u64) {}
+}
+
+// Nothing is connected to the port.
+struct Unconnected;
+
+impl Peripheral for Unconnected {}
+
+// Port shared with the RIOT, which has the same ports without the control lines.
+pub(crate) struct Port {
+    pub(crate) or: Byte,
+    pub(crate) ddr: Byte,
+    latch: Byte, // input latched on the active edge of C1
+
+    peripheral: RefCell<Box<dyn Peripheral + Send>>,
+    pub(crate) pins: Cell<Byte>, // last levels reported to the peripheral
+    c1: bool,                    // last levels of the control lines seen
+    c2: bool,                    // ...
+    c2_out: Cell<bool>,          // level of C2 when it is an output
+    pulse: Cell<bool>,           // C2 is held low for one cycle in the pulse mode
+}
+
+impl Port {
+    pub(crate) fn new() -> Port {
+        Port {
+            or: 0,
+            ddr: 0,
+            latch: 0,
+            peripheral: RefCell::new(Box::new(Unconnected)),
+            pins: Cell::new(0xFF),
+            c1: true,
+            c2: true,
+            c2_out: Cell::new(true),
+            pulse: Cell::new(false),
+        }
+    }
+
+    pub(crate) fn attach(&mut self, peripheral: Box<dyn Peripheral + Send>) {
+        self.c1 = peripheral.c1();
+        self.c2 = peripheral.c2();
+        self.peripheral = RefCell::new(peripheral);
+    }
+
+    fn input(&self) -> Byte {
+        self.peripheral.borrow_mut().read_port()
+    }
+
+    // Value seen on the pins: outputs come from the output register, inputs from the outside.
+    pub(crate) fn read_pins(&self) -> Byte {
+        (self.or & self.ddr) | (self.input() & !self.ddr)
+    }
+
+    pub(crate) fn drive(&self, pins: Byte) {
+        if self.pins.replace(pins) != pins {
+            self.peripheral.borrow_mut().write_port(pins);
+        }
+    }
+
+    fn set_c2(&self, level: bool) {
+        if self.c2_out.replace(level) != level {
+            self.peripheral.borrow_mut().write_c2(level);
+        }
+    }
+
+    pub(crate) fn tick(&mut self, cycles: u64) {
+        self.peripheral.get_mut().tick(cycles);
+    }
+
+    fn pulse_c1(&self) {
+        let mut peripheral = self.peripheral.borrow_mut();
+        peripheral.write_c1(false);
+        peripheral.write_c1(true);
+    }
+}
+
+// MOS 6522 Versatile Interface Adapter: two 8-bit ports with data direction registers,
+// handshake lines CA1/CA2 and CB1/CB2, two 16-bit timers and a shift register, all of which
+// can raise interrupts. Timer 1 runs in one-shot or free-running mode and can drive PB7,
+// timer 2 is one-shot or counts pulses on PB6.
+pub struct Via {
+    a: Port,
+    b: Port,
+
+    ifr: Cell<Byte>,
+    ier: Byte,
+    acr: Byte,
+    pcr: Byte,
+
+    t1_counter: Word,
+    t1_latch: Word,
+    t1_armed: bool,  // one-shot mode interrupts once after the counter is written
+    t1_reload: bool, // the latch is transferred to the counter on the next cycle
+    t1_start: bool,  // the counter has just been written and starts counting on the next cycle
+    pb7: bool,
+
+    t2_counter: Word,
+    t2_latch: Byte,
+    t2_armed: bool,
+    t2_start: bool,
+    pb6: bool,
+
+    sr: Cell<Byte>,
+    sr_bits: Cell<u8>,
+    sr_active: Cell<bool>,
+    sr_timer: Cell<u16>,
+}
+
+impl Default for Via {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Via {
+    pub fn new() -> Via {
+        Via {
+            a: Port::new(),
+            b: Port::new(),
+            ifr: Cell::new(0),
+            ier: 0,
+            acr: 0,
+            pcr: 0,
+            t1_counter: 0,
+            t1_latch: 0,
+            t1_armed: false,
+            t1_reload: false,
+            t1_start: false,
+            pb7: true,
+            t2_counter: 0,
+            t2_latch: 0,
+            t2_armed: false,
+            t2_start: false,
+            pb6: true,
+            sr: Cell::new(0),
+            sr_bits: Cell::new(0),
+            sr_active: Cell::new(false),
+            sr_timer: Cell::new(0),
+        }
+    }
+
+    pub fn attach_a(&mut self, peripheral: Box<dyn Peripheral + Send>) {
+        self.a.attach(peripheral);
+    }
+
+    pub fn attach_b(&mut self, peripheral: Box<dyn Peripheral + Send>) {
+        self.b.attach(peripheral);
+    }
+
+    // Levels the VIA drives port A and B to, with the inputs pulled high.
+    pub fn port_a(&self) -> Byte {
+        self.a.pins.get()
+    }
+
+    pub fn port_b(&self) -> Byte {
+        self.b.pins.get()
+    }
+
+    fn set_flag(&self, flag: Byte) {
+        self.ifr.set(self.ifr.get() | flag);
+    }
+
+    fn clear_flag(&self, flag: Byte) {
+        self.ifr.set(self.ifr.get() & !flag);
+    }
+
+    fn ca2_mode(&self) -> Byte {
+        (self.pcr >> 1) & 0b111
+    }
+
+    fn cb2_mode(&self) -> Byte {
+        (self.pcr >> 5) & 0b111
+    }
+
+    fn sr_mode(&self) -> Byte {
+        (self.acr & ACR_SR_MODE) >> 2
+    }
+
+    fn update_pins(&self) {
+        self.a.drive((self.a.or & self.a.ddr) | !self.a.ddr);
+
+        let mut pins = (self.b.or & self.b.ddr) | !self.b.ddr;
+        if self.acr & ACR_T1_PB7 != 0 {
+            pins = (pins & 0x7F) | ((self.pb7 as Byte) << 7);
+        }
+        self.b.drive(pins);
+    }
+
+    // Lines in the manual output modes follow the PCR right away.
+    fn update_c2(&self) {
+        for (port, mode) in [(&self.a, self.ca2_mode()), (&self.b, self.cb2_mode())] {
+            match mode {
+                C2_LOW => port.set_c2(false),
+                C2_HIGH => port.set_c2(true),
+                _ => {}
+            }
+        }
+    }
+
+    // Reading or writing port A clears its interrupts and does the CA2 handshake.
+    fn access_a(&self) {
+        self.clear_flag(IRQ_CA1);
+        match self.ca2_mode() {
+            C2_INPUT_NEG | C2_INPUT_POS => self.clear_flag(IRQ_CA2),
+            C2_HANDSHAKE => self.a.set_c2(false),
+            C2_PULSE => {
+                self.a.set_c2(false);
+                self.a.pulse.set(true);
+            }
+            _ => {}
+        }
+    }
+
+    // Same for port B, except that the CB2 handshake is only done on writes.
+    fn access_b(&self, write: bool) {
+        self.clear_flag(IRQ_CB1);
+        match self.cb2_mode() {
+            C2_INPUT_NEG | C2_INPUT_POS => self.clear_flag(IRQ_CB2),
+            C2_HANDSHAKE if write => self.b.set_c2(false),
+            C2_PULSE if write => {
+                self.b.set_c2(false);
+                self.b.pulse.set(true);
+            }
+            _ => {}
+        }
+    }
+
+    fn restart_sr(&self) {
+        self.clear_flag(IRQ_SR);
+        self.sr_bits.set(0);
+        self.sr_timer.set(0);
+        self.sr_active.set(self.sr_mode() != SR_DISABLED);
+    }
+
+    // Shifts one bit in or out. Shifting out goes from bit 7 to CB2 and recirculates into bit 0.
+    fn shift(&self, internal_clock: bool) {
+        let mode = self.sr_mode();
+        let sr = self.sr.get();
+        if mode & 0b100 != 0 {
+            self.b.set_c2(sr & 0x80 != 0);
+            if internal_clock {
+                self.b.pulse_c1();
+            }
+            self.sr.set(sr.rotate_left(1));
+        } else {
+            if internal_clock {
+                self.b.pulse_c1();
+            }
+            let bit = self.b.peripheral.borrow().c2() as Byte;
+            self.sr.set((sr << 1) | bit);
+        }
+
+        self.sr_bits.set(self.sr_bits.get() + 1);
+        if self.sr_bits.get() == 8 {
+            self.sr_bits.set(0);
+            if mode != SR_OUT_FREE {
+                self.set_flag(IRQ_SR);
+                self.sr_active.set(false);
+            }
+        }
+    }
+
+    fn step_sr(&self) {
+        if !self.sr_active.get() {
+            return;
+        }
+        // Every bit takes two edges of the clock, which toggles every cycle with φ2
+        // or every time the lower byte of timer 2 runs out.
+        let period = match self.sr_mode() {
+            SR_IN_PHI2 | SR_OUT_PHI2 => 2,
+            SR_IN_T2 | SR_OUT_T2 | SR_OUT_FREE => 2 * (self.t2_latch as u16 + 2),
+            _ => return,
+        };
+        self.sr_timer.set(self.sr_timer.get() + 1);
+        if self.sr_timer.get() >= period {
+            self.sr_timer.set(0);
+            self.shift(true);
+        }
+    }
+
+    fn step_t1(&mut self) {
+        if self.t1_start {
+            self.t1_start = false;
+            return;
+        }
+        if self.t1_reload {
+            self.t1_reload = false;
+            self.t1_counter = self.t1_latch;
+            return;
+        }
+
+        self.t1_counter = self.t1_counter.wrapping_sub(1);
+        if self.t1_counter != 0xFFFF {
+            return;
+        }
+
+        if self.acr & ACR_T1_FREE_RUN != 0 {
+            self.t1_reload = true;
+            self.set_flag(IRQ_T1);
+            self.pb7 = !self.pb7;
+        } else if self.t1_armed {
+            self.t1_armed = false;
+            self.set_flag(IRQ_T1);
+            self.pb7 = true;
+        }
+        if self.acr & ACR_T1_PB7 != 0 {
+            self.update_pins();
+        }
+    }
+
+    fn step_t2(&mut self) {
+        if self.t2_start {
+            self.t2_start = false;
+            return;
+        }
+
+        if self.acr & ACR_T2_PULSES != 0 {
+            let pb6 = self.b.read_pins() & 0x40 != 0;
+            let falling = self.pb6 && !pb6;
+            self.pb6 = pb6;
+            if !falling {
+                return;
+            }
+        }
+
+        // Timed out when rolling past zero, or after counting down the given number of pulses.
+        self.t2_counter = self.t2_counter.wrapping_sub(1);
+        let expired = if self.acr & ACR_T2_PULSES != 0 {
+            self.t2_counter == 0
+        } else {
+            self.t2_counter == 0xFFFF
+        };
+        if expired && self.t2_armed {
+            self.t2_armed = false;
+            self.set_flag(IRQ_T2);
+        }
+    }
+
+    fn step_control(&mut self) {
+        let (ca1, ca2) = {
+            let p = self.a.peripheral.borrow();
+            (p.c1(), p.c2())
+        };
+        let (cb1, cb2) = {
+            let p = self.b.peripheral.borrow();
+            (p.c1(), p.c2())
+        };
+
+        // CA1 and CB1 raise interrupts on the edge selected in the PCR,
+        // latch the port if enabled and complete the handshake.
+        if ca1 != self.a.c1 {
+            self.a.c1 = ca1;
+            if ca1 == (self.pcr & 0b0000_0001 != 0) {
+                self.set_flag(IRQ_CA1);
+                if self.acr & ACR_PA_LATCH != 0 {
+                    self.a.latch = self.a.read_pins();
+                }
+                if self.ca2_mode() == C2_HANDSHAKE {
+                    self.a.set_c2(true);
+                }
+            }
+        }
+
+        if cb1 != self.b.c1 {
+            self.b.c1 = cb1;
+            if cb1 == (self.pcr & 0b0001_0000 != 0) {
+                self.set_flag(IRQ_CB1);
+                if self.acr & ACR_PB_LATCH != 0 {
+                    self.b.latch = self.b.read_pins();
+                }
+                if self.cb2_mode() == C2_HANDSHAKE {
+                    self.b.set_c2(true);
+                }
+            }
+
+            // With the external clock, bits are shifted in on the rising edge and out on the falling one.
+            if self.sr_active.get() {
+                match self.sr_mode() {
+                    SR_IN_EXT if cb1 => self.shift(false),
+                    SR_OUT_EXT if !cb1 => self.shift(false),
+                    _ => {}
+                }
+            }
+        }
+
+        // CA2 and CB2 in the input modes raise interrupts on the selected edge.
+        for (port, mode, level, flag) in [
+            (&mut self.a, (self.pcr >> 1) & 0b111, ca2, IRQ_CA2),
+            (&mut self.b, (self.pcr >> 5) & 0b111, cb2, IRQ_CB2),
+        ] {
+            if level != port.c2 {
+                port.c2 = level;
+                let active = match mode {
+                    C2_INPUT_NEG | C2_INDEPENDENT_NEG => !level,
+                    C2_INPUT_POS | C2_INDEPENDENT_POS => level,
+                    _ => false,
+                };
+                if active {
+                    self.ifr.set(self.ifr.get() | flag);
+                }
+            }
+        }
+    }
+
+    fn step(&mut self) {
+        for port in [&self.a, &self.b] {
+            if port.pulse.replace(false) {
+                port.set_c2(true);
+            }
+        }
+
+        self.step_t1();
+        self.step_t2();
+        self.step_sr();
+        self.step_control();
+
+        self.a.tick(1);
+        self.b.tick(1);
+    }
+}
+
+impl Memory for Via {
+    fn read(&self, addr: Word) -> Byte {
+        match addr & 0x0F {
+            REG_ORB => {
+                self.access_b(false);
+                let input = if self.acr & ACR_PB_LATCH != 0 {
+                    self.b.latch
+                } else {
+                    self.b.input()
+                };
+                let mut value = (self.b.or & self.b.ddr) | (input & !self.b.ddr);
+                if self.acr & ACR_T1_PB7 != 0 {
+                    value = (value & 0x7F) | ((self.pb7 as Byte) << 7);
+                }
+                value
+            }
+            REG_ORA | REG_ORA_NH => {
+                if addr & 0x0F == REG_ORA {
+                    self.access_a();
+                }
+                if self.acr & ACR_PA_LATCH != 0 {
+                    self.a.latch
+                } else {
+                    self.a.read_pins()
+                }
+            }
+            REG_DDRB => self.b.ddr,
+            REG_DDRA => self.a.ddr,
+            REG_T1CL => {
+                self.clear_flag(IRQ_T1);
+                self.t1_counter as Byte
+            }
+            REG_T1CH => (self.t1_counter >> 8) as Byte,
+            REG_T1LL => self.t1_latch as Byte,
+            REG_T1LH => (self.t1_latch >> 8) as Byte,
+            REG_T2CL => {
+                self.clear_flag(IRQ_T2);
+                self.t2_counter as Byte
+            }
+            REG_T2CH => (self.t2_counter >> 8) as Byte,
+            REG_SR => {
+                self.restart_sr();
+                self.sr.get()
+            }
+            REG_ACR => self.acr,
+            REG_PCR => self.pcr,
+            REG_IFR => {
+                let ifr = self.ifr.get();
+                if ifr & self.ier & 0x7F != 0 {
+                    ifr | IRQ_ANY
+                } else {
+                    ifr
+                }
+            }
+            REG_IER => self.ier | 0x80,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        match addr & 0x0F {
+            REG_ORB => {
+                self.b.or = data;
+                self.access_b(true);
+            }
+            REG_ORA => {
+                self.a.or = data;
+                self.access_a();
+            }
+            REG_ORA_NH => self.a.or = data,
+            REG_DDRB => self.b.ddr = data,
+            REG_DDRA => self.a.ddr = data,
+            REG_T1CL | REG_T1LL => self.t1_latch = (self.t1_latch & 0xFF00) | data as Word,
+            REG_T1CH => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | (data as Word) << 8;
+                self.t1_counter = self.t1_latch;
+                self.t1_armed = true;
+                self.t1_reload = false;
+                self.t1_start = true;
+                self.clear_flag(IRQ_T1);
+                if self.acr & ACR_T1_PB7 != 0 {
+                    self.pb7 = false;
+                }
+            }
+            REG_T1LH => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | (data as Word) << 8;
+                self.clear_flag(IRQ_T1);
+            }
+            REG_T2CL => self.t2_latch = data,
+            REG_T2CH => {
+                self.t2_counter = (data as Word) << 8 | self.t2_latch as Word;
+                self.t2_armed = true;
+                self.t2_start = true;
+                self.clear_flag(IRQ_T2);
+            }
+            REG_SR => {
+                self.sr.set(data);
+                self.restart_sr();
+            }
+            REG_ACR => {
+                self.acr = data;
+                if self.sr_mode() == SR_DISABLED {
+                    self.sr_active.set(false);
+                }
+            }
+            REG_PCR => {
+                self.pcr = data;
+                self.update_c2();
+            }
+            REG_IFR => self.clear_flag(data & 0x7F),
+            REG_IER => {
+                if data & 0x80 != 0 {
+                    self.ier |= data & 0x7F;
+                } else {
+                    self.ier &= !data;
+                }
+            }
+            _ => unreachable!(),
+        }
+        self.update_pins();
+    }
+}
+
+impl Device for Via {
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.step();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.ifr.get() & self.ier & 0x7F != 0
+    }
+}
+
+#[cfg(test)]
+mod via_test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Peripheral that records everything the VIA does and lets the test drive the inputs.
+    #[derive(Default)]
+    struct State {
+        writes: Vec<Byte>,
+        input: Byte,
+        c1: bool,
+        c2: bool,
+        c1_out: Vec<bool>,
+        c2_out: Vec<bool>,
+    }
+
+    #[derive(Clone)]
+    struct Probe(Arc<Mutex<State>>);
+
+    impl Probe {
+        fn new() -> Probe {
+            Probe(Arc::new(Mutex::new(State {
+                input: 0xFF,
+                c1: true,
+                c2: true,
+                ..Default::default()
+            })))
+        }
+
+        fn state(&self) -> std::sync::MutexGuard<'_, State> {
+            self.0.lock().unwrap()
+        }
+    }
+
+    impl Peripheral for Probe {
+        fn write_port(&mut self, pins: Byte) {
+            self.state().writes.push(pins);
+        }
+
+        fn read_port(&mut self) -> Byte {
+            self.state().input
+        }
+
+        fn c1(&self) -> bool {
+            self.state().c1
+        }
+
+        fn c2(&self) -> bool {
+            self.state().c2
+        }
+
+        fn write_c1(&mut self, level: bool) {
+            self.state().c1_out.push(level);
+        }
+
+        fn write_c2(&mut self, level: bool) {
+            self.state().c2_out.push(level);
+        }
+    }
+
+    fn setup() -> (Via, Probe, Probe) {
+        let (a, b) = (Probe::new(), Probe::new());
+        let mut via = Via::new();
+        via.attach_a(Box::new(a.clone()));
+        via.attach_b(Box::new(b.clone()));
+        (via, a, b)
+    }
+
+    #[test]
+    fn port_directions() {
+        let (mut via, _, b) = setup();
+        b.state().input = 0b1010_1010;
+
+        via.write(REG_DDRB, 0x0F);
+        via.write(REG_ORB, 0x55);
+        assert_eq!(via.port_b(), 0xF5);
+        assert_eq!(*b.state().writes.last().unwrap(), 0xF5);
+
+        // outputs read back from the register, inputs from the pins
+        assert_eq!(via.read(REG_ORB), 0b1010_0101);
+    }
+
+    #[test]
+    fn port_a_reads_pins() {
+        let (mut via, a, _) = setup();
+        a.state().input = 0x3C;
+        via.write(REG_DDRA, 0xF0);
+        via.write(REG_ORA, 0xA5);
+        assert_eq!(via.read(REG_ORA), 0xAC);
+    }
+
+    #[test]
+    fn interrupt_enable() {
+        let mut via = Via::new();
+        via.write(REG_IER, 0x80 | IRQ_T1 | IRQ_CA1);
+        assert_eq!(via.read(REG_IER), 0x80 | IRQ_T1 | IRQ_CA1);
+        via.write(REG_IER, IRQ_CA1);
+        assert_eq!(via.read(REG_IER), 0x80 | IRQ_T1);
+
+        via.set_flag(IRQ_CA1);
+        assert_eq!(via.read(REG_IFR), IRQ_CA1);
+        assert!(!via.irq());
+
+        via.set_flag(IRQ_T1);
+        assert_eq!(via.read(REG_IFR), IRQ_ANY | IRQ_T1 | IRQ_CA1);
+        assert!(via.irq());
+
+        via.write(REG_IFR, IRQ_T1);
+        assert_eq!(via.read(REG_IFR), IRQ_CA1);
+        assert!(!via.irq());
+    }
+
+    #[test]
+    fn t1_one_shot() {
+        let mut via = Via::new();
+        via.write(REG_IER, 0x80 | IRQ_T1);
+        via.write(REG_T1CL, 0x10);
+        via.write(REG_T1CH, 0x00);
+
+        // the counter goes 0x10..0, then 0xFFFF raises the interrupt at N + 2
+        via.tick(0x11);
+        assert_eq!(via.read(REG_T1CL), 0x00);
+        assert!(!via.irq());
+        via.tick(1);
+        assert!(via.irq());
+
+        // reading the low counter clears the flag, and it does not fire again
+        via.read(REG_T1CL);
+        assert!(!via.irq());
+        via.tick(0x20000);
+        assert!(!via.irq());
+    }
+
+    #[test]
+    fn t1_free_run() {
+        let (mut via, _, b) = setup();
+        via.write(REG_ACR, ACR_T1_FREE_RUN | ACR_T1_PB7);
+        via.write(REG_T1CL, 0x08);
+        via.write(REG_T1CH, 0x00);
+        assert_eq!(via.port_b() & 0x80, 0);
+
+        // every period is N + 2 cycles and toggles PB7
+        via.tick(10);
+        assert_eq!(via.read(REG_IFR) & IRQ_T1, IRQ_T1);
+        assert_eq!(via.port_b() & 0x80, 0x80);
+
+        via.write(REG_IFR, IRQ_T1);
+        via.tick(9);
+        assert_eq!(via.read(REG_IFR) & IRQ_T1, 0);
+        via.tick(1);
+        assert_eq!(via.read(REG_IFR) & IRQ_T1, IRQ_T1);
+        assert_eq!(via.port_b() & 0x80, 0);
+
+        assert_eq!(b.state().writes, vec![0x7F, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn t2_one_shot() {
+        let mut via = Via::new();
+        via.write(REG_T2CL, 0x05);
+        via.write(REG_T2CH, 0x00);
+
+        via.tick(6);
+        assert_eq!(via.read(REG_IFR) & IRQ_T2, 0);
+        via.tick(1);
+        assert_eq!(via.read(REG_IFR) & IRQ_T2, IRQ_T2);
+
+        via.read(REG_T2CL);
+        via.tick(0x10000);
+        assert_eq!(via.read(REG_IFR) & IRQ_T2, 0);
+    }
+
+    #[test]
+    fn t2_pulse_counting() {
+        let (mut via, _, b) = setup();
+        via.write(REG_ACR, ACR_T2_PULSES);
+        via.write(REG_T2CL, 0x03);
+        via.write(REG_T2CH, 0x00);
+        via.tick(10);
+
+        for _ in 0..2 {
+            b.state().input = 0xBF;
+            via.tick(1);
+            b.state().input = 0xFF;
+            via.tick(1);
+        }
+        assert_eq!(via.read(REG_IFR) & IRQ_T2, 0);
+
+        b.state().input = 0xBF;
+        via.tick(1);
+        assert_eq!(via.read(REG_IFR) & IRQ_T2, IRQ_T2);
+    }
+
+    #[test]
+    fn ca1_interrupt_and_latch() {
+        let (mut via, a, _) = setup();
+        via.write(REG_ACR, ACR_PA_LATCH);
+        via.write(REG_PCR, 0b0000_0001); // positive edge
+
+        a.state().c1 = false;
+        via.tick(1);
+        assert_eq!(via.read(REG_IFR) & IRQ_CA1, 0);
+
+        a.state().input = 0x42;
+        a.state().c1 = true;
+        via.tick(1);
+        assert_eq!(via.read(REG_IFR) & IRQ_CA1, IRQ_CA1);
+
+        // the latched value is returned even though the pins have changed
+        a.state().input = 0x00;
+        assert_eq!(via.read(REG_ORA), 0x42);
+        assert_eq!(via.read(REG_IFR) & IRQ_CA1, 0);
+    }
+
+    #[test]
+    fn ca2_handshake() {
+        let (mut via, a, _) = setup();
+        via.write(REG_PCR, C2_HANDSHAKE << 1); // CA1 negative edge
+
+        via.write(REG_ORA, 0x01);
+        assert_eq!(a.state().c2_out, vec![false]);
+
+        a.state().c1 = false;
+        via.tick(1);
+        assert_eq!(a.state().c2_out, vec![false, true]);
+    }
+
+    #[test]
+    fn ca2_pulse() {
+        let (mut via, a, _) = setup();
+        via.write(REG_PCR, C2_PULSE << 1);
+        via.read(REG_ORA);
+        assert_eq!(a.state().c2_out, vec![false]);
+        via.tick(1);
+        assert_eq!(a.state().c2_out, vec![false, true]);
+
+        // no handshake without the flag clearing register
+        via.read(REG_ORA_NH);
+        via.tick(1);
+        assert_eq!(a.state().c2_out.len(), 2);
+    }
+
+    #[test]
+    fn cb2_manual_output() {
+        let (mut via, _, b) = setup();
+        via.write(REG_PCR, C2_LOW << 5);
+        via.write(REG_PCR, C2_HIGH << 5);
+        assert_eq!(b.state().c2_out, vec![false, true]);
+    }
+
+    #[test]
+    fn cb2_input_interrupt() {
+        let (mut via, _, b) = setup();
+        via.write(REG_PCR, C2_INDEPENDENT_NEG << 5);
+        b.state().c2 = false;
+        via.tick(1);
+        assert_eq!(via.read(REG_IFR) & IRQ_CB2, IRQ_CB2);
+
+        // independent interrupts are not cleared by the port access
+        via.read(REG_ORB);
+        assert_eq!(via.read(REG_IFR) & IRQ_CB2, IRQ_CB2);
+        assert_eq!(via.read(REG_IFR) & IRQ_CB1, 0);
+    }
+
+    #[test]
+    fn shift_out_phi2() {
+        let (mut via, _, b) = setup();
+        via.write(REG_ACR, SR_OUT_PHI2 << 2);
+        via.write(REG_SR, 0b1011_0010);
+
+        via.tick(16);
+        assert_eq!(via.read(REG_IFR) & IRQ_SR, IRQ_SR);
+        assert_eq!(b.state().c1_out.len(), 16);
+
+        // CB2 starts high, so only the changes are reported: 1 -> 0 -> 1 -> 0 -> 1 -> 0
+        assert_eq!(b.state().c2_out, vec![false, true, false, true, false]);
+        assert_eq!(via.sr.get(), 0b1011_0010);
+
+        via.tick(16);
+        assert_eq!(b.state().c1_out.len(), 16);
+    }
+
+    #[test]
+    fn shift_in_external() {
+        let (mut via, _, b) = setup();
+        via.write(REG_ACR, SR_IN_EXT << 2);
+        via.read(REG_SR);
+
+        for bit in [1, 0, 1, 0, 0, 1, 1, 0] {
+            b.state().c2 = bit == 1;
+            b.state().c1 = false;
+            via.tick(1);
+            b.state().c1 = true;
+            via.tick(1);
+        }
+        assert_eq!(via.read(REG_IFR) & IRQ_SR, IRQ_SR);
+        assert_eq!(via.read(REG_SR), 0b1010_0110);
+    }
+
+    #[test]
+    fn shift_free_running() {
+        let mut via = Via::new();
+        via.write(REG_T2CL, 0);
+        via.write(REG_ACR, SR_OUT_FREE << 2);
+        via.write(REG_SR, 0x81);
+
+        via.tick(4 * 8);
+        assert_eq!(via.sr.get(), 0x81);
+        assert_eq!(via.read(REG_IFR) & IRQ_SR, 0);
+        assert!(via.sr_active.get());
+    }
+}
@@ -2,6 +2,7 @@
 ;compile with xa program.s -o program
 
 stdout    = $0200
+exit      = $FFF9
 *         = $0300
 
 len:    .byte 12
@@ -16,8 +17,8 @@ loop:
         bne loop
         lda #$0A            ;line break
         sta stdout+$ff      ;writing to $02ff triggers the output
-spin:
-        jmp spin            ;there is no way to exit the program
+        lda #$00
+        sta exit            ;stop the VM with exit code 0
 
 
 tail:
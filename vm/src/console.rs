@@ -0,0 +1,134 @@
+use mos6502::mem::{Device, Memory};
+use mos6502::types::*;
+use std::io;
+
+const REG_DATA: Word = 0x00;
+const REG_STATUS: Word = 0x01;
+
+const STATUS_READY: Byte = 0b1000_0000; // a byte can be written
+const STATUS_ERROR: Byte = 0b0100_0000; // writing to the host has failed
+
+// Console prints one character at a time. Unlike Stdout, there is no buffer: every byte
+// written to the data register goes to the host right away. The status register tells
+// whether the console is ready, which it always is, and whether an output error has occurred.
+pub struct Console {
+    out: Box<dyn io::Write + Send>,
+    crlf: bool,
+    last_cr: bool,
+    error: bool,
+}
+
+impl Console {
+    pub fn new(out: Box<dyn io::Write + Send>) -> Self {
+        Self {
+            out,
+            crlf: false,
+            last_cr: false,
+            error: false,
+        }
+    }
+
+    // Makes CR, LF and CR LF written by the program all end up as a single host newline,
+    // for programs written for machines that use CR as the line terminator.
+    pub fn with_crlf(mut self, crlf: bool) -> Self {
+        self.crlf = crlf;
+        self
+    }
+
+    fn put(&mut self, data: Byte) {
+        let data = if self.crlf {
+            let last_cr = std::mem::replace(&mut self.last_cr, data == b'\r');
+            match data {
+                b'\r' => b'\n',
+                b'\n' if last_cr => return,
+                _ => data,
+            }
+        } else {
+            data
+        };
+
+        if self
+            .out
+            .write_all(&[data])
+            .and_then(|_| self.out.flush())
+            .is_err()
+        {
+            self.error = true;
+        }
+    }
+}
+
+impl Memory for Console {
+    fn read(&self, addr: Word) -> Byte {
+        match addr {
+            REG_STATUS if self.error => STATUS_READY | STATUS_ERROR,
+            REG_STATUS => STATUS_READY,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        if addr == REG_DATA {
+            self.put(data);
+        }
+    }
+}
+
+impl Device for Console {}
+
+#[cfg(test)]
+mod console_test {
+    use super::*;
+    use crate::terminal::Buffer;
+
+    fn output(console: &mut Console, data: &[u8]) {
+        for &b in data {
+            console.write(REG_DATA, b);
+        }
+    }
+
+    #[test]
+    fn writes_immediately() {
+        let buf = Buffer::default();
+        let mut console = Console::new(Box::new(buf.clone()));
+
+        console.write(REG_DATA, b'A');
+        assert_eq!(buf.contents(), b"A");
+
+        output(&mut console, b"B\r\n");
+        assert_eq!(buf.contents(), b"AB\r\n");
+        assert_eq!(console.read(REG_STATUS), STATUS_READY);
+    }
+
+    #[test]
+    fn crlf_translation() {
+        let buf = Buffer::default();
+        let mut console = Console::new(Box::new(buf.clone())).with_crlf(true);
+
+        output(&mut console, b"a\r\nb\rc\nd\r\r\n");
+        assert_eq!(buf.contents(), b"a\nb\nc\nd\n\n");
+    }
+
+    #[test]
+    fn output_error() {
+        let mut console = Console::new(Box::new(io::sink()));
+        console.write(REG_DATA, b'A');
+        assert_eq!(console.read(REG_STATUS), STATUS_READY);
+
+        let mut console = Console::new(Box::new(Failing));
+        console.write(REG_DATA, b'A');
+        assert_eq!(console.read(REG_STATUS), STATUS_READY | STATUS_ERROR);
+    }
+
+    struct Failing;
+
+    impl io::Write for Failing {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use mos6502::mem::{Device, Memory};
+use mos6502::types::*;
+use std::sync::{Arc, Mutex};
+
+// Lets the program stop the VM. Writing a byte anywhere in the range of the device halts
+// the emulation, and the byte becomes the exit status of the process.
+pub struct Exit {
+    status: ExitStatus,
+}
+
+// Shared between the device and the VM, which checks it after every cycle.
+#[derive(Clone, Default)]
+pub struct ExitStatus(Arc<Mutex<Option<Byte>>>);
+
+impl ExitStatus {
+    pub fn get(&self) -> Option<Byte> {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl Exit {
+    pub fn new(status: ExitStatus) -> Self {
+        Self { status }
+    }
+}
+
+impl Memory for Exit {
+    fn read(&self, _addr: Word) -> Byte {
+        0
+    }
+
+    fn write(&mut self, _addr: Word, data: Byte) {
+        self.status.0.lock().unwrap().get_or_insert(data);
+    }
+}
+
+impl Device for Exit {
+    // Loading an image over the device must not stop the VM.
+    fn poke(&mut self, _addr: Word, _data: Byte) {}
+}
+
+#[cfg(test)]
+mod exit_test {
+    use super::*;
+
+    #[test]
+    fn first_write_wins() {
+        let status = ExitStatus::default();
+        let mut exit = Exit::new(status.clone());
+        assert_eq!(status.get(), None);
+
+        exit.write(0x0000, 3);
+        exit.write(0x0000, 0);
+        assert_eq!(status.get(), Some(3));
+    }
+}
@@ -0,0 +1,513 @@
+use mos6502::mem::{Device, Dma, Memory};
+use mos6502::types::*;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+const REG_NAME: Word = 0x00; // 2 bytes, address of the file name
+const REG_MODE: Word = 0x02;
+const REG_CHANNEL: Word = 0x03;
+const REG_BUFFER: Word = 0x04; // 2 bytes
+const REG_LENGTH: Word = 0x06; // 2 bytes
+const REG_OFFSET: Word = 0x08; // 4 bytes
+const REG_COMMAND: Word = 0x0C; // status when read
+const REG_DATA: Word = 0x0D; // port the DMA transfers go through
+
+pub const REGISTERS: usize = REG_DATA as usize + 1;
+
+const CMD_OPEN: Byte = 0x01;
+const CMD_CLOSE: Byte = 0x02;
+const CMD_READ: Byte = 0x03;
+const CMD_WRITE: Byte = 0x04;
+const CMD_SEEK: Byte = 0x05;
+
+const MODE_READ: Byte = 0x00;
+const MODE_WRITE: Byte = 0x01; // created or truncated
+const MODE_APPEND: Byte = 0x02; // created if missing
+const MODE_UPDATE: Byte = 0x03; // read and write an existing file
+
+const STATUS_OK: Byte = 0x00;
+const STATUS_NOT_FOUND: Byte = 0x01;
+const STATUS_DENIED: Byte = 0x02;
+const STATUS_NOT_OPEN: Byte = 0x03;
+const STATUS_IO_ERROR: Byte = 0x04;
+const STATUS_INVALID: Byte = 0x05;
+const STATUS_BUSY: Byte = 0x80;
+
+const CHANNELS: usize = 4;
+
+// Command waiting for its DMA transfer to complete.
+enum Pending {
+    None,
+    Open(Vec<Byte>),
+    Write(Vec<Byte>),
+    Read(RefCell<VecDeque<Byte>>),
+}
+
+// Gives the program access to the files in a directory on the host, without emulating a disk
+// controller. The program fills in the registers and writes a command: OPEN takes a file name
+// of LENGTH bytes at NAME and opens it in one of the modes on the selected channel, READ and WRITE
+// transfer up to LENGTH bytes between the file and BUFFER, SEEK moves to OFFSET, and CLOSE closes
+// the file. The data is moved by DMA, and when the command is done, the status register is zero or
+// holds an error, and LENGTH holds the number of bytes transferred (zero at the end of a file).
+// File names must stay inside the directory.
+pub struct Files {
+    root: PathBuf,
+    base: Word, // address of the device, where the DMA transfers are directed to
+
+    name: Word,
+    mode: Byte,
+    channel: Byte,
+    buffer: Word,
+    length: Word,
+    offset: u32,
+    status: Cell<Byte>,
+
+    files: [Option<File>; CHANNELS],
+    pending: Pending,
+    dma: Option<Dma>,
+}
+
+impl Files {
+    pub fn new(root: PathBuf, base: Word) -> Self {
+        Self {
+            root,
+            base,
+            name: 0,
+            mode: 0,
+            channel: 0,
+            buffer: 0,
+            length: 0,
+            offset: 0,
+            status: Cell::new(STATUS_OK),
+            files: Default::default(),
+            pending: Pending::None,
+            dma: None,
+        }
+    }
+
+    fn command(&mut self, cmd: Byte) {
+        if self.status.get() == STATUS_BUSY {
+            return;
+        }
+        let channel = self.channel as usize;
+        if channel >= CHANNELS {
+            self.status.set(STATUS_INVALID);
+            return;
+        }
+        if matches!(cmd, CMD_READ | CMD_WRITE | CMD_SEEK) && self.files[channel].is_none() {
+            self.status.set(STATUS_NOT_OPEN);
+            return;
+        }
+
+        match cmd {
+            CMD_OPEN if self.length > 0 => {
+                let port = self.base.wrapping_add(REG_DATA);
+                self.start(
+                    Pending::Open(Vec::new()),
+                    Dma::to_port(self.name, port, self.length),
+                );
+            }
+            CMD_CLOSE => {
+                self.files[channel] = None;
+                self.status.set(STATUS_OK);
+            }
+            CMD_READ => {
+                let mut data = vec![0; self.length as usize];
+                let result = read_full(self.files[channel].as_mut().unwrap(), &mut data);
+                self.finish(result.map(|n| {
+                    data.truncate(n);
+                    data
+                }));
+            }
+            CMD_WRITE if self.length > 0 => {
+                let port = self.base.wrapping_add(REG_DATA);
+                self.start(
+                    Pending::Write(Vec::new()),
+                    Dma::to_port(self.buffer, port, self.length),
+                );
+            }
+            CMD_WRITE => self.status.set(STATUS_OK),
+            CMD_SEEK => {
+                let file = self.files[channel].as_mut().unwrap();
+                let result = file.seek(SeekFrom::Start(self.offset as u64));
+                self.status.set(status(result.map(|_| ())));
+            }
+            _ => self.status.set(STATUS_INVALID),
+        }
+    }
+
+    fn start(&mut self, pending: Pending, dma: Dma) {
+        self.pending = pending;
+        self.dma = Some(dma);
+        self.status.set(STATUS_BUSY);
+    }
+
+    // Hands the data read from a file over to the DMA.
+    fn finish(&mut self, result: io::Result<Vec<Byte>>) {
+        match result {
+            Ok(data) if data.is_empty() => {
+                self.length = 0;
+                self.status.set(STATUS_OK);
+            }
+            Ok(data) => {
+                self.length = data.len() as Word;
+                let port = self.base.wrapping_add(REG_DATA);
+                let dma = Dma::from_port(port, self.buffer, self.length);
+                self.start(Pending::Read(RefCell::new(data.into())), dma);
+            }
+            Err(err) => self.status.set(status(Err(err))),
+        }
+    }
+
+    // Receives a byte from the DMA, and runs the command when all of them are there.
+    fn receive(&mut self, data: Byte) {
+        let channel = self.channel as usize;
+        let length = self.length as usize;
+        match &mut self.pending {
+            Pending::Open(name) => {
+                name.push(data);
+                if name.len() == length {
+                    let name = std::mem::take(name);
+                    self.pending = Pending::None;
+                    let result = self.open(&name);
+                    self.status.set(match result {
+                        Ok(file) => {
+                            self.files[channel] = Some(file);
+                            STATUS_OK
+                        }
+                        Err(status) => status,
+                    });
+                }
+            }
+            Pending::Write(buf) => {
+                buf.push(data);
+                if buf.len() == length {
+                    let buf = std::mem::take(buf);
+                    self.pending = Pending::None;
+                    let file = self.files[channel].as_mut().unwrap();
+                    self.status.set(status(file.write_all(&buf)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn open(&self, name: &[Byte]) -> Result<File, Byte> {
+        let path = self.resolve(name)?;
+        let mut options = OpenOptions::new();
+        match self.mode {
+            MODE_READ => options.read(true),
+            MODE_WRITE => options.write(true).create(true).truncate(true),
+            MODE_APPEND => options.append(true).create(true),
+            MODE_UPDATE => options.read(true).write(true),
+            _ => return Err(STATUS_INVALID),
+        };
+        options.open(path).map_err(|err| status(Err(err)))
+    }
+
+    // Only plain relative names are accepted, and symbolic links are followed to make sure
+    // that the file is inside the directory, so the program cannot escape it. A file that does
+    // not exist yet is checked by its parent directory.
+    fn resolve(&self, name: &[Byte]) -> Result<PathBuf, Byte> {
+        let name = std::str::from_utf8(name).map_err(|_| STATUS_INVALID)?;
+        let path = Path::new(name);
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(STATUS_DENIED);
+        }
+
+        let root = self.root.canonicalize().map_err(|err| status(Err(err)))?;
+        let path = self.root.join(path);
+        let path = match path.canonicalize() {
+            Ok(path) => path,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let parent = path.parent().ok_or(STATUS_INVALID)?;
+                let parent = parent.canonicalize().map_err(|err| status(Err(err)))?;
+                let path = parent.join(path.file_name().ok_or(STATUS_INVALID)?);
+                // a dangling link would let a new file be created wherever it points
+                if path.symlink_metadata().is_ok() {
+                    return Err(STATUS_DENIED);
+                }
+                path
+            }
+            Err(err) => return Err(status(Err(err))),
+        };
+        if !path.starts_with(&root) {
+            return Err(STATUS_DENIED);
+        }
+        Ok(path)
+    }
+}
+
+// Reads until the buffer is full or the file ends.
+fn read_full(file: &mut File, buf: &mut [Byte]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match file.read(&mut buf[total..]) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+fn status(result: io::Result<()>) -> Byte {
+    match result {
+        Ok(()) => STATUS_OK,
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => STATUS_NOT_FOUND,
+            io::ErrorKind::PermissionDenied => STATUS_DENIED,
+            _ => STATUS_IO_ERROR,
+        },
+    }
+}
+
+impl Memory for Files {
+    fn read(&self, addr: Word) -> Byte {
+        match addr {
+            REG_NAME => self.name as Byte,
+            0x01 => (self.name >> 8) as Byte,
+            REG_MODE => self.mode,
+            REG_CHANNEL => self.channel,
+            REG_BUFFER => self.buffer as Byte,
+            0x05 => (self.buffer >> 8) as Byte,
+            REG_LENGTH => self.length as Byte,
+            0x07 => (self.length >> 8) as Byte,
+            REG_OFFSET..=0x0B => (self.offset >> (8 * (addr - REG_OFFSET))) as Byte,
+            REG_COMMAND => self.status.get(),
+            REG_DATA => match &self.pending {
+                Pending::Read(data) => data.borrow_mut().pop_front().unwrap_or(0),
+                _ => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        match addr {
+            REG_NAME => self.name = (self.name & 0xFF00) | data as Word,
+            0x01 => self.name = (self.name & 0x00FF) | (data as Word) << 8,
+            REG_MODE => self.mode = data,
+            REG_CHANNEL => self.channel = data,
+            REG_BUFFER => self.buffer = (self.buffer & 0xFF00) | data as Word,
+            0x05 => self.buffer = (self.buffer & 0x00FF) | (data as Word) << 8,
+            REG_LENGTH => self.length = (self.length & 0xFF00) | data as Word,
+            0x07 => self.length = (self.length & 0x00FF) | (data as Word) << 8,
+            REG_OFFSET..=0x0B => {
+                let shift = 8 * (addr - REG_OFFSET);
+                self.offset = (self.offset & !(0xFF << shift)) | (data as u32) << shift;
+            }
+            REG_COMMAND => self.command(data),
+            REG_DATA => self.receive(data),
+            _ => {}
+        }
+    }
+}
+
+impl Device for Files {
+    fn tick(&mut self, _cycles: u64) {
+        // The last byte of a read is written to memory in the cycle after it has been taken
+        // from the port, so the command is done one cycle later.
+        if let Pending::Read(data) = &self.pending {
+            if data.borrow().is_empty() {
+                self.pending = Pending::None;
+                self.status.set(STATUS_OK);
+            }
+        }
+    }
+
+    fn take_dma(&mut self) -> Option<Dma> {
+        self.dma.take()
+    }
+
+    // Loading an image over the device must not run commands.
+    fn poke(&mut self, _addr: Word, _data: Byte) {}
+}
+
+#[cfg(test)]
+mod files_test {
+    use super::*;
+    use mos6502::bus::SyncBus;
+    use mos6502::mem::Ram;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    const BASE: Word = 0xFF00;
+
+    struct Setup {
+        bus: SyncBus,
+        root: PathBuf,
+    }
+
+    impl Setup {
+        fn new(test: &str) -> Setup {
+            let root =
+                std::env::temp_dir().join(format!("vm-files-{}-{}", std::process::id(), test));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+
+            let mut bus = SyncBus::new();
+            let files = Files::new(root.clone(), BASE);
+            bus.plug_in((BASE, BASE + 0x0D), Arc::new(Mutex::new(files)))
+                .unwrap();
+            bus.plug_in((0x0000, 0xFFFF), Arc::new(Mutex::new(Ram::new())))
+                .unwrap();
+            Setup { bus, root }
+        }
+
+        fn set(&mut self, reg: Word, value: Word) {
+            self.bus.write(BASE + reg, value as Byte);
+            self.bus.write(BASE + reg + 1, (value >> 8) as Byte);
+        }
+
+        // Runs a command and waits for the DMA, like a program polling the status would.
+        fn run(&mut self, cmd: Byte) -> Byte {
+            self.bus.write(BASE + REG_COMMAND, cmd);
+            for _ in 0..0x20000 {
+                self.bus.tick(1);
+                if self.bus.read(BASE + REG_COMMAND) != STATUS_BUSY {
+                    break;
+                }
+            }
+            self.bus.read(BASE + REG_COMMAND)
+        }
+
+        fn open(&mut self, name: &str, mode: Byte) -> Byte {
+            self.bus.load(0x1000, name.as_bytes()).unwrap();
+            self.set(REG_NAME, 0x1000);
+            self.set(REG_LENGTH, name.len() as Word);
+            self.bus.write(BASE + REG_MODE, mode);
+            self.run(CMD_OPEN)
+        }
+
+        fn length(&self) -> Word {
+            self.bus.read(BASE + REG_LENGTH) as Word
+                | (self.bus.read(BASE + REG_LENGTH + 1) as Word) << 8
+        }
+    }
+
+    impl Drop for Setup {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn write_and_read() {
+        let mut vm = Setup::new("write_and_read");
+        assert_eq!(vm.open("out.txt", MODE_WRITE), STATUS_OK);
+
+        vm.bus.load(0x2000, b"hello, world").unwrap();
+        vm.set(REG_BUFFER, 0x2000);
+        vm.set(REG_LENGTH, 12);
+        assert_eq!(vm.run(CMD_WRITE), STATUS_OK);
+        assert_eq!(vm.run(CMD_CLOSE), STATUS_OK);
+        assert_eq!(fs::read(vm.root.join("out.txt")).unwrap(), b"hello, world");
+
+        assert_eq!(vm.open("out.txt", MODE_READ), STATUS_OK);
+        vm.set(REG_BUFFER, 0x3000);
+        vm.set(REG_LENGTH, 5);
+        assert_eq!(vm.run(CMD_READ), STATUS_OK);
+        assert_eq!(vm.length(), 5);
+        for (i, &b) in b"hello".iter().enumerate() {
+            assert_eq!(vm.bus.read(0x3000 + i as Word), b);
+        }
+
+        // the rest of the file, then nothing at the end
+        vm.set(REG_LENGTH, 100);
+        assert_eq!(vm.run(CMD_READ), STATUS_OK);
+        assert_eq!(vm.length(), 7);
+        assert_eq!(vm.bus.read(0x3006), b'd');
+        assert_eq!(vm.bus.read(0x3007), 0);
+        vm.set(REG_LENGTH, 100);
+        assert_eq!(vm.run(CMD_READ), STATUS_OK);
+        assert_eq!(vm.length(), 0);
+    }
+
+    #[test]
+    fn seek_and_append() {
+        let mut vm = Setup::new("seek_and_append");
+        fs::write(vm.root.join("data"), b"0123456789").unwrap();
+
+        assert_eq!(vm.open("data", MODE_UPDATE), STATUS_OK);
+        vm.set(REG_OFFSET, 4);
+        assert_eq!(vm.run(CMD_SEEK), STATUS_OK);
+        vm.bus.load(0x2000, b"xy").unwrap();
+        vm.set(REG_BUFFER, 0x2000);
+        vm.set(REG_LENGTH, 2);
+        assert_eq!(vm.run(CMD_WRITE), STATUS_OK);
+        assert_eq!(fs::read(vm.root.join("data")).unwrap(), b"0123xy6789");
+
+        assert_eq!(vm.open("data", MODE_APPEND), STATUS_OK);
+        vm.set(REG_LENGTH, 2);
+        assert_eq!(vm.run(CMD_WRITE), STATUS_OK);
+        assert_eq!(fs::read(vm.root.join("data")).unwrap(), b"0123xy6789xy");
+    }
+
+    #[test]
+    fn channels() {
+        let mut vm = Setup::new("channels");
+        fs::write(vm.root.join("in"), b"abc").unwrap();
+
+        vm.bus.write(BASE + REG_CHANNEL, 1);
+        assert_eq!(vm.open("in", MODE_READ), STATUS_OK);
+        vm.bus.write(BASE + REG_CHANNEL, 2);
+        assert_eq!(vm.open("out", MODE_WRITE), STATUS_OK);
+
+        vm.bus.write(BASE + REG_CHANNEL, 1);
+        vm.set(REG_BUFFER, 0x2000);
+        vm.set(REG_LENGTH, 3);
+        assert_eq!(vm.run(CMD_READ), STATUS_OK);
+        vm.bus.write(BASE + REG_CHANNEL, 2);
+        assert_eq!(vm.run(CMD_WRITE), STATUS_OK);
+        assert_eq!(fs::read(vm.root.join("out")).unwrap(), b"abc");
+
+        vm.bus.write(BASE + REG_CHANNEL, 0);
+        assert_eq!(vm.run(CMD_READ), STATUS_NOT_OPEN);
+        vm.bus.write(BASE + REG_CHANNEL, 4);
+        assert_eq!(vm.run(CMD_CLOSE), STATUS_INVALID);
+    }
+
+    #[test]
+    fn errors() {
+        let mut vm = Setup::new("errors");
+        assert_eq!(vm.open("missing", MODE_READ), STATUS_NOT_FOUND);
+        assert_eq!(vm.open("../escape", MODE_WRITE), STATUS_DENIED);
+        assert_eq!(vm.open("/etc/passwd", MODE_READ), STATUS_DENIED);
+        assert_eq!(vm.open("file", 0x10), STATUS_INVALID);
+        assert_eq!(vm.run(0x7F), STATUS_INVALID);
+        assert!(!vm.root.join("file").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_escape() {
+        let mut vm = Setup::new("symlink_escape");
+        let outside = vm.root.with_extension("outside");
+        let _ = fs::remove_dir_all(&outside);
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret"), b"secret").unwrap();
+        std::os::unix::fs::symlink(&outside, vm.root.join("dir")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret"), vm.root.join("file")).unwrap();
+        fs::create_dir(vm.root.join("inside")).unwrap();
+        std::os::unix::fs::symlink(vm.root.join("inside"), vm.root.join("ok")).unwrap();
+        std::os::unix::fs::symlink(outside.join("new"), vm.root.join("dangling")).unwrap();
+
+        assert_eq!(vm.open("file", MODE_READ), STATUS_DENIED);
+        assert_eq!(vm.open("dir/secret", MODE_UPDATE), STATUS_DENIED);
+        assert_eq!(vm.open("dir/new", MODE_WRITE), STATUS_DENIED);
+        assert_eq!(vm.open("dangling", MODE_WRITE), STATUS_DENIED);
+        assert_eq!(vm.open("dangling", MODE_APPEND), STATUS_DENIED);
+        assert!(!outside.join("new").exists());
+
+        // links that stay inside are fine
+        assert_eq!(vm.open("ok/new", MODE_WRITE), STATUS_OK);
+        assert!(vm.root.join("inside/new").exists());
+        assert_eq!(vm.open("ok/missing/new", MODE_WRITE), STATUS_NOT_FOUND);
+        fs::remove_dir_all(&outside).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+use mos6502::mem::{Device, Memory};
+use mos6502::types::*;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::png;
+
+// The 16 colours of easy6502, which are the ones of the Commodore 64.
+pub const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], // black
+    [0xFF, 0xFF, 0xFF], // white
+    [0x88, 0x00, 0x00], // red
+    [0xAA, 0xFF, 0xEE], // cyan
+    [0xCC, 0x44, 0xCC], // purple
+    [0x00, 0xCC, 0x55], // green
+    [0x00, 0x00, 0xAA], // blue
+    [0xEE, 0xEE, 0x77], // yellow
+    [0xDD, 0x88, 0x55], // orange
+    [0x66, 0x44, 0x00], // brown
+    [0xFF, 0x77, 0x77], // light red
+    [0x33, 0x33, 0x33], // dark grey
+    [0x77, 0x77, 0x77], // grey
+    [0xAA, 0xFF, 0x66], // light green
+    [0x00, 0x88, 0xFF], // light blue
+    [0xBB, 0xBB, 0xBB], // light grey
+];
+
+const FRAME_RATE: f64 = 30.0; // how often the terminal is redrawn, in emulated time
+
+// How the screen is drawn on the terminal, in 24-bit colour.
+pub enum Render {
+    HalfBlocks, // two pixels per character, one above the other
+    Blocks,     // two characters per pixel, so that the pixels are square
+}
+
+// Video RAM with one byte per pixel, the lower four bits of which select a colour from
+// the palette. Writing to the byte right after the pixels saves a PNG snapshot
+// of the screen, and one more snapshot is saved when the device is dropped.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    vram: Vec<Byte>,
+
+    terminal: Option<(Render, Box<dyn io::Write + Send>)>,
+    shown: Option<Vec<Byte>>,
+    frame_cycles: u64,
+    cycles: u64,
+
+    snapshot: Option<PathBuf>,
+    snapshots: usize,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            vram: vec![0; width * height],
+            terminal: None,
+            shown: None,
+            frame_cycles: 0,
+            cycles: 0,
+            snapshot: None,
+            snapshots: 0,
+        }
+    }
+
+    pub fn with_terminal(
+        mut self,
+        render: Render,
+        out: Box<dyn io::Write + Send>,
+        cpu_frequency: f32,
+    ) -> Self {
+        self.terminal = Some((render, out));
+        self.frame_cycles = (cpu_frequency as f64 * 1_000_000.0 / FRAME_RATE) as u64;
+        self
+    }
+
+    // Snapshots requested by the program are numbered (screen-1.png, screen-2.png, ...),
+    // the one taken at the end goes to the given path.
+    pub fn with_snapshot(mut self, path: PathBuf) -> Self {
+        self.snapshot = Some(path);
+        self
+    }
+
+    pub fn png(&self) -> Vec<u8> {
+        let pixels: Vec<u8> = self.vram.iter().map(|&pixel| pixel & 0x0F).collect();
+        png::encode(self.width, self.height, &PALETTE, &pixels)
+    }
+
+    fn save(&self, path: &Path) {
+        if let Err(err) = fs::write(path, self.png()) {
+            eprintln!("failed to save {}: {}", path.display(), err);
+        }
+    }
+
+    fn numbered(path: &Path, n: usize) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        match path.extension() {
+            Some(ext) => path.with_file_name(format!("{}-{}.{}", stem, n, ext.to_string_lossy())),
+            None => path.with_file_name(format!("{}-{}", stem, n)),
+        }
+    }
+
+    fn color(&self, x: usize, y: usize) -> Option<[u8; 3]> {
+        (y < self.height).then(|| PALETTE[self.vram[y * self.width + x] as usize & 0x0F])
+    }
+
+    fn draw(&mut self) {
+        if self.shown.as_ref() == Some(&self.vram) {
+            return;
+        }
+        let Some((render, _)) = &self.terminal else {
+            return;
+        };
+
+        let mut lines = Vec::new();
+        match render {
+            Render::HalfBlocks => {
+                for y in (0..self.height).step_by(2) {
+                    let mut line = String::new();
+                    for x in 0..self.width {
+                        let [r, g, b] = self.color(x, y).unwrap();
+                        let _ = write!(line, "\x1b[38;2;{};{};{}m", r, g, b);
+                        match self.color(x, y + 1) {
+                            Some([r, g, b]) => {
+                                let _ = write!(line, "\x1b[48;2;{};{};{}m▀", r, g, b);
+                            }
+                            None => line.push_str("\x1b[49m▀"),
+                        }
+                    }
+                    lines.push(line);
+                }
+            }
+            Render::Blocks => {
+                for y in 0..self.height {
+                    let mut line = String::new();
+                    for x in 0..self.width {
+                        let [r, g, b] = self.color(x, y).unwrap();
+                        let _ = write!(line, "\x1b[48;2;{};{};{}m  ", r, g, b);
+                    }
+                    lines.push(line);
+                }
+            }
+        }
+
+        let mut buf = String::new();
+        if self.shown.is_some() {
+            let _ = write!(buf, "\x1b[{}A\r", lines.len());
+        }
+        for line in lines {
+            let _ = writeln!(buf, "{}\x1b[0m", line);
+        }
+
+        let (_, out) = self.terminal.as_mut().unwrap();
+        let _ = out.write_all(buf.as_bytes());
+        let _ = out.flush();
+        self.shown = Some(self.vram.clone());
+    }
+}
+
+impl Memory for Framebuffer {
+    fn read(&self, addr: Word) -> Byte {
+        self.vram.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        match self.vram.get_mut(addr as usize) {
+            Some(pixel) => *pixel = data,
+            None => {
+                if let Some(path) = &self.snapshot {
+                    self.snapshots += 1;
+                    self.save(&Self::numbered(path, self.snapshots));
+                }
+            }
+        }
+    }
+}
+
+impl Device for Framebuffer {
+    fn tick(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        if self.terminal.is_some() && self.cycles >= self.frame_cycles {
+            self.cycles = 0;
+            self.draw();
+        }
+    }
+
+    // Loading an image over the snapshot register must not take a snapshot.
+    fn poke(&mut self, addr: Word, data: Byte) {
+        if let Some(pixel) = self.vram.get_mut(addr as usize) {
+            *pixel = data;
+        }
+    }
+}
+
+// The VM drops the devices when it stops, so this is where the last frame is shown and saved.
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        self.draw();
+        if let Some(path) = &self.snapshot {
+            self.save(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod framebuffer_test {
+    use super::*;
+    use crate::terminal::Buffer;
+
+    #[test]
+    fn half_blocks() {
+        let out = Buffer::default();
+        let mut fb = Framebuffer::new(2, 3).with_terminal(
+            Render::HalfBlocks,
+            Box::new(out.clone()),
+            0.000_030, // one frame per cycle
+        );
+        fb.write(0x00, 0x01);
+        fb.write(0x03, 0xF2); // only the lower nibble matters
+        fb.tick(1);
+
+        let screen = out.take();
+        let lines: Vec<_> = screen.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m▀\x1b[38;2;0;0;0m\x1b[48;2;136;0;0m▀\x1b[0m"
+        );
+        assert_eq!(
+            lines[1],
+            "\x1b[38;2;0;0;0m\x1b[49m▀\x1b[38;2;0;0;0m\x1b[49m▀\x1b[0m"
+        );
+
+        // nothing is drawn until the screen changes, and then it is redrawn in place
+        fb.tick(1);
+        assert_eq!(out.take(), "");
+        fb.write(0x05, 0x01);
+        fb.tick(1);
+        assert!(out.take().starts_with("\x1b[2A\r"));
+    }
+
+    #[test]
+    fn blocks() {
+        let out = Buffer::default();
+        let mut fb =
+            Framebuffer::new(2, 1).with_terminal(Render::Blocks, Box::new(out.clone()), 1.0);
+        fb.write(0x01, 0x06);
+        fb.tick(33332);
+        assert_eq!(out.take(), "");
+        fb.tick(1);
+        assert_eq!(
+            out.take(),
+            "\x1b[48;2;0;0;0m  \x1b[48;2;0;0;170m  \x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn snapshots() {
+        let dir = std::env::temp_dir().join(format!("framebuffer-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut fb = Framebuffer::new(4, 4).with_snapshot(dir.join("screen.png"));
+        fb.write(0x05, 0x03);
+        let png = fb.png();
+        fb.write(0x10, 0x00);
+        fb.write(0x00, 0x01);
+        fb.poke(0x10, 0x00);
+        drop(fb);
+
+        assert_eq!(fs::read(dir.join("screen-1.png")).unwrap(), png);
+        assert!(!dir.join("screen-2.png").exists());
+        assert_ne!(fs::read(dir.join("screen.png")).unwrap(), png);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use mos6502::mem::{Device, Memory};
+use mos6502::types::*;
+use std::cell::Cell;
+use std::sync::mpsc::Receiver;
+
+const REG_DATA: Word = 0x00;
+const REG_STATUS: Word = 0x01;
+
+const STATUS_AVAILABLE: Byte = 0b1000_0000; // a byte is waiting in the data register
+
+// Keyboard receives bytes typed on the host. The status register tells whether a byte is
+// available, and reading the data register takes it. Optionally, the IRQ line is held while
+// a byte is waiting, so the program does not have to poll.
+pub struct Keyboard {
+    input: Receiver<Byte>,
+    pending: Cell<Option<Byte>>,
+    irq: bool,
+    crlf: bool,
+}
+
+impl Keyboard {
+    pub fn new(input: Receiver<Byte>) -> Self {
+        Self {
+            input,
+            pending: Cell::new(None),
+            irq: false,
+            crlf: false,
+        }
+    }
+
+    pub fn with_irq(mut self, irq: bool) -> Self {
+        self.irq = irq;
+        self
+    }
+
+    // Turns LF into CR, for programs that expect the return key to produce CR
+    // while the input comes from a pipe or a file.
+    pub fn with_crlf(mut self, crlf: bool) -> Self {
+        self.crlf = crlf;
+        self
+    }
+
+    fn poll(&self) -> Option<Byte> {
+        if self.pending.get().is_none() {
+            let data = self.input.try_recv().ok();
+            self.pending.set(match data {
+                Some(b'\n') if self.crlf => Some(b'\r'),
+                _ => data,
+            });
+        }
+        self.pending.get()
+    }
+}
+
+impl Memory for Keyboard {
+    fn read(&self, addr: Word) -> Byte {
+        match addr {
+            REG_DATA => {
+                self.poll();
+                self.pending.take().unwrap_or(0)
+            }
+            REG_STATUS if self.poll().is_some() => STATUS_AVAILABLE,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, _addr: Word, _data: Byte) {}
+}
+
+impl Device for Keyboard {
+    fn tick(&mut self, _cycles: u64) {
+        self.poll();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq && self.pending.get().is_some()
+    }
+}
+
+#[cfg(test)]
+mod keyboard_test {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn read_input() {
+        let (tx, rx) = channel();
+        let kbd = Keyboard::new(rx);
+        assert_eq!(kbd.read(REG_STATUS), 0);
+        assert_eq!(kbd.read(REG_DATA), 0);
+
+        tx.send(b'a').unwrap();
+        tx.send(b'b').unwrap();
+        assert_eq!(kbd.read(REG_STATUS), STATUS_AVAILABLE);
+        assert_eq!(kbd.read(REG_DATA), b'a');
+        assert_eq!(kbd.read(REG_STATUS), STATUS_AVAILABLE);
+        assert_eq!(kbd.read(REG_DATA), b'b');
+        assert_eq!(kbd.read(REG_STATUS), 0);
+    }
+
+    #[test]
+    fn irq() {
+        let (tx, rx) = channel();
+        let mut kbd = Keyboard::new(rx).with_irq(true);
+        kbd.tick(1);
+        assert!(!kbd.irq());
+
+        tx.send(b'a').unwrap();
+        kbd.tick(1);
+        assert!(kbd.irq());
+
+        kbd.read(REG_DATA);
+        assert!(!kbd.irq());
+    }
+
+    #[test]
+    fn crlf() {
+        let (tx, rx) = channel();
+        let kbd = Keyboard::new(rx).with_crlf(true);
+        tx.send(b'\n').unwrap();
+        assert_eq!(kbd.read(REG_DATA), b'\r');
+    }
+}
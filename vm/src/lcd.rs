@@ -0,0 +1,204 @@
+use mos6502::lcd::Lcd;
+use mos6502::types::*;
+use mos6502::via::{Peripheral, Via};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+// LCD that draws itself in a box on the terminal. The box is redrawn in place
+// every time the contents of the screen change.
+pub struct Display {
+    lcd: Lcd,
+    out: Box<dyn io::Write + Send>,
+    shown: Option<Vec<String>>,
+}
+
+impl Display {
+    pub fn new(lcd: Lcd, out: Box<dyn io::Write + Send>) -> Self {
+        Self {
+            lcd,
+            out,
+            shown: None,
+        }
+    }
+
+    fn update(&mut self) {
+        let screen = self.lcd.screen();
+        if self.shown.as_ref() == Some(&screen) {
+            return;
+        }
+
+        let mut buf = String::new();
+        if self.shown.is_some() {
+            buf.push_str(&format!("\x1b[{}A\r", screen.len() + 2));
+        }
+        let border = "─".repeat(self.lcd.columns());
+        buf.push_str(&format!("┌{}┐\n", border));
+        for row in &screen {
+            buf.push_str(&format!("│{}│\n", row));
+        }
+        buf.push_str(&format!("└{}┘\n", border));
+
+        // Nobody may be watching the terminal, so a failed write is not fatal.
+        let _ = self.out.write_all(buf.as_bytes());
+        let _ = self.out.flush();
+        self.shown = Some(screen);
+    }
+}
+
+type SharedDisplay = Arc<Mutex<Display>>;
+
+// Ben Eater's 8-bit wiring: D0-D7 on port B, and E, RW and RS on PA7, PA6 and PA5.
+pub fn connect_8bit(via: &mut Via, display: Display) {
+    let display = Arc::new(Mutex::new(display));
+    via.attach_a(Box::new(Control(display.clone())));
+    via.attach_b(Box::new(Data(display)));
+}
+
+// Ben Eater's 4-bit wiring, everything on port B: D4-D7 on PB0-PB3, and E, RW and RS
+// on PB6, PB5 and PB4, which leaves port A free for other peripherals.
+pub fn connect_4bit(via: &mut Via, display: Display) {
+    via.attach_b(Box::new(Nibbles(Arc::new(Mutex::new(display)))));
+}
+
+struct Control(SharedDisplay);
+
+impl Peripheral for Control {
+    fn write_port(&mut self, pins: Byte) {
+        let mut display = self.0.lock().unwrap();
+        display
+            .lcd
+            .set_control(pins & 0x20 != 0, pins & 0x40 != 0, pins & 0x80 != 0);
+        display.update();
+    }
+}
+
+struct Data(SharedDisplay);
+
+impl Peripheral for Data {
+    fn write_port(&mut self, pins: Byte) {
+        self.0.lock().unwrap().lcd.set_data(pins);
+    }
+
+    fn read_port(&mut self) -> Byte {
+        self.0.lock().unwrap().lcd.output().unwrap_or(0xFF)
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.0.lock().unwrap().lcd.tick(cycles);
+    }
+}
+
+struct Nibbles(SharedDisplay);
+
+impl Peripheral for Nibbles {
+    fn write_port(&mut self, pins: Byte) {
+        let mut display = self.0.lock().unwrap();
+        display.lcd.set_data((pins & 0x0F) << 4);
+        display
+            .lcd
+            .set_control(pins & 0x10 != 0, pins & 0x20 != 0, pins & 0x40 != 0);
+        display.update();
+    }
+
+    fn read_port(&mut self) -> Byte {
+        match self.0.lock().unwrap().lcd.output() {
+            Some(data) => (data >> 4) | 0xF0,
+            None => 0xFF,
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.0.lock().unwrap().lcd.tick(cycles);
+    }
+}
+
+#[cfg(test)]
+mod lcd_test {
+    use super::*;
+    use crate::terminal::Buffer;
+    use mos6502::mem::{Device, Memory};
+
+    const PORTB: Word = 0x00;
+    const PORTA: Word = 0x01;
+    const DDRB: Word = 0x02;
+    const DDRA: Word = 0x03;
+
+    const E: Byte = 0x80;
+    const RW: Byte = 0x40;
+    const RS: Byte = 0x20;
+
+    // What Ben Eater's hello world program does, including the busy flag polling.
+    fn send(via: &mut Via, rs: Byte, data: Byte) {
+        via.write(DDRB, 0x00);
+        loop {
+            via.write(PORTA, RW);
+            via.write(PORTA, RW | E);
+            let status = via.read(PORTB);
+            via.write(PORTA, RW);
+            via.tick(1);
+            if status & 0x80 == 0 {
+                break;
+            }
+        }
+        via.write(DDRB, 0xFF);
+        via.write(PORTB, data);
+        via.write(PORTA, rs);
+        via.write(PORTA, rs | E);
+        via.write(PORTA, rs);
+    }
+
+    #[test]
+    fn hello_world_8bit() {
+        let out = Buffer::default();
+        let mut via = Via::new();
+        connect_8bit(&mut via, Display::new(Lcd::new(1.0), Box::new(out.clone())));
+
+        via.write(DDRA, E | RW | RS);
+        for cmd in [0b0011_1000, 0b0000_1100, 0b0000_0110, 0b0000_0001] {
+            send(&mut via, 0, cmd);
+        }
+        for b in "Hello, world!".bytes() {
+            send(&mut via, RS, b);
+        }
+
+        let out = String::from_utf8(out.contents()).unwrap();
+        let last = out.rsplit("\x1b[4A\r").next().unwrap();
+        assert_eq!(
+            last,
+            "┌────────────────┐\n\
+             │Hello, world!   │\n\
+             │                │\n\
+             └────────────────┘\n"
+        );
+    }
+
+    #[test]
+    fn busy_flag_4bit() {
+        let mut via = Via::new();
+        connect_4bit(&mut via, Display::new(Lcd::new(1.0), Box::new(io::sink())));
+        via.write(DDRB, 0xFF);
+
+        let pulse = |via: &mut Via, bits: Byte| {
+            via.write(PORTB, bits);
+            via.write(PORTB, bits | 0x40);
+            via.write(PORTB, bits);
+        };
+
+        // switch to 4 bits, then clear the display in two halves with RS low
+        for nibble in [0x2, 0x0, 0x1] {
+            pulse(&mut via, nibble);
+        }
+
+        // read the status with D4-D7 as inputs, the busy flag comes first on PB3
+        via.write(DDRB, 0xF0);
+        via.write(PORTB, 0x20);
+        via.write(PORTB, 0x20 | 0x40);
+        assert_eq!(via.read(PORTB) & 0x08, 0x08);
+        via.write(PORTB, 0x20);
+        pulse(&mut via, 0x20);
+
+        via.tick(2000);
+        via.write(PORTB, 0x20 | 0x40);
+        assert_eq!(via.read(PORTB) & 0x08, 0x00);
+    }
+}
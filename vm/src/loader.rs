@@ -0,0 +1,164 @@
+use std::fs;
+use std::path::Path;
+
+use mos6502::bus::{Bus, DeviceRef};
+use mos6502::image::{self, Format, Image};
+use mos6502::types::*;
+use mos6502::{ihex, o65, prg, srec};
+
+// A file to be placed into memory.
+pub struct ImageFile {
+    pub path: String,
+    pub image: Image,
+}
+
+impl ImageFile {
+    pub fn open(path: &str, addr: Option<Word>, zero: Option<Byte>) -> Result<ImageFile, String> {
+        let data = fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+        let image = parse(detect(Path::new(path), &data), &data, addr, zero)
+            .map_err(|err| format!("{}: {}", path, err))?;
+
+        Ok(ImageFile {
+            path: path.to_string(),
+            image,
+        })
+    }
+}
+
+// The format is detected by the contents of the file, except for PRG files,
+// which have no magic number and are recognized by the extension.
+pub fn detect(path: &Path, data: &[Byte]) -> Format {
+    let is_prg = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("prg"));
+    if is_prg {
+        Format::Prg
+    } else {
+        image::detect(data)
+    }
+}
+
+// Intel HEX, S-record and PRG files carry their own addresses and ignore the given one.
+// Raw binaries are loaded at the given address, which is required for them. o65 objects
+// are relocated to it, or stay where they were assembled for when it is missing, and their
+// zero page segment is moved to the given zero page address, if any.
+pub fn parse(
+    format: Format,
+    data: &[Byte],
+    addr: Option<Word>,
+    zero: Option<Byte>,
+) -> Result<Image, String> {
+    match format {
+        Format::Binary => {
+            let addr = addr.ok_or("raw binary needs a load address")?;
+            Image::binary(addr, data)
+        }
+        Format::IntelHex => ihex::parse(&String::from_utf8_lossy(data)),
+        Format::SRecord => srec::parse(&String::from_utf8_lossy(data)),
+        Format::Prg => prg::parse(data),
+        Format::O65 => o65::parse(data).and_then(|obj| {
+            let layout = addr.map_or(obj.base, |addr| obj.layout_at(addr, zero));
+            obj.relocate(&layout)
+        }),
+    }
+}
+
+// Makes sure that no two segments are loaded into the same memory.
+pub fn check_overlaps(files: &[ImageFile]) -> Result<(), String> {
+    let mut segments: Vec<_> = files
+        .iter()
+        .flat_map(|file| file.image.segments.iter().map(move |seg| (file, seg)))
+        .filter(|(_, seg)| !seg.data.is_empty())
+        .collect();
+    segments.sort_by_key(|(_, seg)| seg.addr);
+
+    for pair in segments.windows(2) {
+        let ((a, sa), (b, sb)) = (pair[0], pair[1]);
+        if sa.end() > sb.addr as usize {
+            return Err(format!(
+                "{} (0x{:04X}-0x{:04X}) overlaps {} (0x{:04X}-0x{:04X})",
+                a.path,
+                sa.addr,
+                sa.end() - 1,
+                b.path,
+                sb.addr,
+                sb.end() - 1,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Loads the files into memory, after making sure that they do not overlap.
+pub fn load_images<D: DeviceRef>(bus: &mut Bus<D>, files: &[ImageFile]) -> Result<(), String> {
+    check_overlaps(files)?;
+    for file in files {
+        file.image
+            .load_into(bus)
+            .map_err(|err| format!("failed to load {}: {}", file.path, err))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod loader_test {
+    use super::*;
+    use mos6502::mem::{Memory, Ram};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn file(path: &str, addr: Word, data: &[Byte]) -> ImageFile {
+        ImageFile {
+            path: path.to_string(),
+            image: Image::binary(addr, data).unwrap(),
+        }
+    }
+
+    #[test]
+    fn raw_binary() {
+        let image = parse(Format::Binary, &[1, 2, 3], Some(0xC000), None).unwrap();
+        assert_eq!(image, Image::binary(0xC000, &[1, 2, 3]).unwrap());
+        assert!(parse(Format::Binary, &[1, 2, 3], None, None).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(parse(Format::Binary, &[0; 0x10], Some(0xFFF0), None).is_ok());
+        assert!(parse(Format::Binary, &[0; 0x11], Some(0xFFF0), None).is_err());
+    }
+
+    #[test]
+    fn overlaps() {
+        let a = file("a.bin", 0x0200, &[0; 0x10]);
+        let b = file("b.bin", 0x0210, &[0; 0x10]);
+        assert!(check_overlaps(&[a, b]).is_ok());
+
+        let a = file("a.bin", 0x0200, &[0; 0x10]);
+        let b = file("b.bin", 0x020F, &[0; 0x10]);
+        assert_eq!(
+            check_overlaps(&[b, a]),
+            Err("a.bin (0x0200-0x020F) overlaps b.bin (0x020F-0x021E)".to_string())
+        );
+    }
+
+    #[test]
+    fn load() {
+        let mut bus: Bus = Bus::new();
+        bus.plug_in((0x0000, 0xFFFF), Rc::new(RefCell::new(Ram::new())))
+            .unwrap();
+
+        let files = [
+            file("a.bin", 0x0200, &[1, 2]),
+            file("b.bin", 0xFFFE, &[3, 4]),
+        ];
+        load_images(&mut bus, &files).unwrap();
+        assert_eq!(bus.read(0x0201), 2);
+        assert_eq!(bus.read(0xFFFF), 4);
+
+        // nothing is loaded when the images overlap
+        let files = [file("a.bin", 0x0300, &[5, 6]), file("b.bin", 0x0301, &[7])];
+        assert!(load_images(&mut bus, &files).is_err());
+        assert_eq!(bus.read(0x0300), 0);
+    }
+}
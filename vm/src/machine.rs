@@ -0,0 +1,607 @@
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use mos6502::acia::Acia;
+use mos6502::bus::SyncBus;
+use mos6502::image::Format;
+use mos6502::lcd::Lcd;
+use mos6502::mem::{Ram, Rom};
+use mos6502::psg::Psg;
+use mos6502::riot::Riot;
+use mos6502::types::*;
+use mos6502::via::Via;
+
+use crate::console::Console;
+use crate::exit::{Exit, ExitStatus};
+use crate::files::{self, Files};
+use crate::framebuffer::{self, Framebuffer};
+use crate::keyboard::Keyboard;
+use crate::lcd::{self, Display};
+use crate::loader;
+use crate::rtc::Rtc;
+use crate::serial;
+use crate::sound::Sound;
+use crate::stdout::Stdout;
+use crate::terminal::Terminal;
+use crate::timer::Timer;
+
+// The machine the VM emulates when no configuration is given: 64K of RAM with the stdout
+// window on top of it, and everything above 0x0300 write-protected to act as ROM.
+pub const DEFAULT_MACHINE: &str = r#"
+[cpu]
+variant = "6502"
+frequency = 1.0
+
+[[device]]
+type = "stdout"
+range = [0x0200, 0x02FF]
+
+[[device]]
+type = "exit"
+range = [0xFFF9, 0xFFF9]
+
+[[device]]
+type = "ram"
+range = [0x0000, 0xFFFF]
+protect = [[0x0300, 0xFFFF]]
+"#;
+
+// Machine describes the CPU and the devices attached to the bus. Devices are plugged in
+// in the order they are declared, so when ranges are nested, the first one wins.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Machine {
+    #[serde(default)]
+    pub cpu: CpuConfig,
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+
+    // Relative paths in the configuration are resolved against this directory.
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CpuConfig {
+    #[serde(default)]
+    pub variant: CpuVariant,
+    #[serde(default = "default_frequency")]
+    pub frequency: f32, // MHz
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        Self {
+            variant: CpuVariant::default(),
+            frequency: default_frequency(),
+        }
+    }
+}
+
+fn default_frequency() -> f32 {
+    1.0
+}
+
+// Only the original NMOS 6502 is emulated for now.
+#[derive(Debug, Default, Deserialize)]
+pub enum CpuVariant {
+    #[default]
+    #[serde(rename = "6502")]
+    Nmos6502,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum DeviceConfig {
+    Ram {
+        range: MemRange,
+        size: Option<usize>,
+        #[serde(default)]
+        protect: Vec<MemRange>,
+    },
+    Rom {
+        range: MemRange,
+        image: PathBuf,
+    },
+    Stdout {
+        range: MemRange,
+    },
+    Console {
+        range: MemRange,
+        #[serde(default)]
+        crlf: bool,
+        file: Option<PathBuf>,
+    },
+    Keyboard {
+        range: MemRange,
+        #[serde(default)]
+        irq: bool,
+        #[serde(default)]
+        crlf: bool,
+    },
+    Acia {
+        range: MemRange,
+        #[serde(default)]
+        backend: SerialBackend,
+        port: Option<u16>,
+    },
+    Via {
+        range: MemRange,
+        lcd: Option<LcdConfig>,
+    },
+    Riot {
+        range: MemRange,
+    },
+    Timer {
+        range: MemRange,
+    },
+    Rtc {
+        range: MemRange,
+    },
+    Files {
+        range: MemRange,
+        root: PathBuf,
+    },
+    Framebuffer {
+        range: MemRange,
+        #[serde(default = "default_framebuffer_size")]
+        size: (usize, usize), // width and height in pixels
+        #[serde(default)]
+        render: FramebufferRender,
+        snapshot: Option<PathBuf>,
+    },
+    Psg {
+        range: MemRange,
+        output: PathBuf,
+        #[serde(default = "default_psg_clock")]
+        clock: f32, // MHz
+        #[serde(default = "default_sample_rate")]
+        sample_rate: u32,
+    },
+    Exit {
+        range: MemRange,
+    },
+}
+
+// Where the serial line of an ACIA is connected to on the host.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialBackend {
+    #[default]
+    Terminal,
+    Pty,
+    Tcp,
+}
+
+// Character LCD connected to the ports of a VIA, wired as in Ben Eater's 6502 computer.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LcdConfig {
+    #[serde(default)]
+    pub wiring: LcdWiring,
+    #[serde(default = "default_lcd_size")]
+    pub size: (usize, usize), // columns and rows
+}
+
+fn default_lcd_size() -> (usize, usize) {
+    (16, 2)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub enum LcdWiring {
+    #[default]
+    #[serde(rename = "8bit")]
+    EightBit,
+    #[serde(rename = "4bit")]
+    FourBit,
+}
+
+fn default_framebuffer_size() -> (usize, usize) {
+    (32, 32)
+}
+
+fn default_psg_clock() -> f32 {
+    3.579545 // NTSC colour burst, as in the Sega Master System
+}
+
+// The chip is stepped on every CPU cycle, so a much faster clock would only stall the VM.
+const MAX_PSG_CLOCK: f32 = 20.0; // MHz
+
+fn default_sample_rate() -> u32 {
+    44100
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FramebufferRender {
+    #[default]
+    HalfBlocks,
+    Blocks,
+    None,
+}
+
+impl DeviceConfig {
+    pub fn range(&self) -> MemRange {
+        match self {
+            DeviceConfig::Ram { range, .. } => *range,
+            DeviceConfig::Rom { range, .. } => *range,
+            DeviceConfig::Stdout { range } => *range,
+            DeviceConfig::Console { range, .. } => *range,
+            DeviceConfig::Keyboard { range, .. } => *range,
+            DeviceConfig::Acia { range, .. } => *range,
+            DeviceConfig::Via { range, .. } => *range,
+            DeviceConfig::Riot { range } => *range,
+            DeviceConfig::Timer { range } => *range,
+            DeviceConfig::Rtc { range } => *range,
+            DeviceConfig::Files { range, .. } => *range,
+            DeviceConfig::Framebuffer { range, .. } => *range,
+            DeviceConfig::Psg { range, .. } => *range,
+            DeviceConfig::Exit { range } => *range,
+        }
+    }
+}
+
+impl Machine {
+    pub fn parse(config: &str) -> Result<Machine, String> {
+        let machine: Machine = toml::from_str(config).map_err(|err| err.to_string())?;
+        let frequency = machine.cpu.frequency;
+        if !frequency.is_finite() || frequency <= 0.0 {
+            return Err(format!(
+                "cpu: frequency must be positive, got {}",
+                frequency
+            ));
+        }
+        Ok(machine)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Machine, String> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+        let mut machine =
+            Self::parse(&config).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if let Some(dir) = path.parent() {
+            machine.base_dir = dir.to_path_buf();
+        }
+        Ok(machine)
+    }
+
+    // Creates all the devices and plugs them into a new bus. RAM is filled with the given byte.
+    // With a seed, the clock and the random numbers of the RTC are the same on every run.
+    pub fn build_bus(
+        &self,
+        terminal: &Terminal,
+        exit: &ExitStatus,
+        fill: Byte,
+        seed: Option<u64>,
+    ) -> Result<SyncBus, String> {
+        let mut bus = SyncBus::new();
+
+        for (i, config) in self.devices.iter().enumerate() {
+            let (start, end) = config.range();
+            let range_len = end as usize - start as usize + 1;
+
+            match config {
+                DeviceConfig::Ram {
+                    range,
+                    size,
+                    protect,
+                } => {
+                    let size = size.unwrap_or(range_len);
+                    let mut ram = Ram::from_buffer(vec![fill; size])
+                        .map_err(|err| format!("device #{}: {}", i + 1, err))?;
+                    for &(start, end) in protect {
+                        if start < range.0 || end > range.1 || start > end {
+                            return Err(format!(
+                                "device #{}: protected range 0x{:04X}-0x{:04X} is out of the device",
+                                i + 1,
+                                start,
+                                end,
+                            ));
+                        }
+                        ram.write_protect((start - range.0, end - range.0));
+                    }
+                    bus.plug_in(*range, Arc::new(Mutex::new(ram)))
+                }
+                DeviceConfig::Rom { range, image } => {
+                    let path = self.base_dir.join(image);
+                    let data = fs::read(&path)
+                        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+                    // Images with addresses are burned into a chip as large as the range,
+                    // with the unused bytes left erased. o65 objects are relocated to its start.
+                    let rom = match loader::detect(&path, &data) {
+                        Format::Binary => Rom::new(&data),
+                        format => loader::parse(format, &data, Some(start), None)
+                            .and_then(|image| image.to_rom(start, range_len, 0xFF))
+                            .map_err(|err| {
+                                format!("device #{}: {}: {}", i + 1, path.display(), err)
+                            })?,
+                    };
+                    if rom.len() > range_len {
+                        return Err(format!(
+                            "device #{}: {} does not fit into 0x{:04X}-0x{:04X}",
+                            i + 1,
+                            path.display(),
+                            start,
+                            end,
+                        ));
+                    }
+                    bus.plug_in(*range, Arc::new(Mutex::new(rom)))
+                }
+                DeviceConfig::Stdout { range } => {
+                    let stdout = Stdout::new(Box::new(terminal.output()));
+                    bus.plug_in(*range, Arc::new(Mutex::new(stdout)))
+                }
+                DeviceConfig::Console { range, crlf, file } => {
+                    let out: Box<dyn io::Write + Send> = match file {
+                        Some(file) => {
+                            let path = self.base_dir.join(file);
+                            let file = File::create(&path).map_err(|err| {
+                                format!("failed to create {}: {}", path.display(), err)
+                            })?;
+                            Box::new(file)
+                        }
+                        None => Box::new(terminal.output()),
+                    };
+                    let console = Console::new(out).with_crlf(*crlf);
+                    bus.plug_in(*range, Arc::new(Mutex::new(console)))
+                }
+                DeviceConfig::Keyboard { range, irq, crlf } => {
+                    let keyboard = Keyboard::new(terminal.input())
+                        .with_irq(*irq)
+                        .with_crlf(*crlf);
+                    bus.plug_in(*range, Arc::new(Mutex::new(keyboard)))
+                }
+                DeviceConfig::Acia {
+                    range,
+                    backend,
+                    port,
+                } => {
+                    let link = match backend {
+                        SerialBackend::Terminal => Ok(serial::terminal(terminal)),
+                        SerialBackend::Pty => serial::pty().map(|(link, path)| {
+                            eprintln!("ACIA at 0x{:04X} is connected to {}", start, path);
+                            link
+                        }),
+                        SerialBackend::Tcp => match port {
+                            Some(port) => serial::tcp(*port).inspect(|_| {
+                                eprintln!("ACIA at 0x{:04X} listens on 127.0.0.1:{}", start, port);
+                            }),
+                            None => Err("tcp backend requires a port".to_string()),
+                        },
+                    }
+                    .map_err(|err| format!("device #{}: {}", i + 1, err))?;
+                    let acia = Acia::new(Box::new(link), self.cpu.frequency);
+                    bus.plug_in(*range, Arc::new(Mutex::new(acia)))
+                }
+                DeviceConfig::Via { range, lcd } => {
+                    let mut via = Via::new();
+                    if let Some(LcdConfig { wiring, size }) = lcd {
+                        // The controller has two lines of 40 characters, which four-line
+                        // modules split in halves.
+                        let (columns, rows) = *size;
+                        if columns == 0 || !(1..=4).contains(&rows) || columns * rows > 80 {
+                            return Err(format!(
+                                "device #{}: unsupported lcd size {}x{}",
+                                i + 1,
+                                columns,
+                                rows
+                            ));
+                        }
+                        let lcd = Lcd::new(self.cpu.frequency).with_size(columns, rows);
+                        let display = Display::new(lcd, Box::new(terminal.output()));
+                        match wiring {
+                            LcdWiring::EightBit => lcd::connect_8bit(&mut via, display),
+                            LcdWiring::FourBit => lcd::connect_4bit(&mut via, display),
+                        }
+                    }
+                    bus.plug_in(*range, Arc::new(Mutex::new(via)))
+                }
+                DeviceConfig::Riot { range } => {
+                    bus.plug_in(*range, Arc::new(Mutex::new(Riot::new())))
+                }
+                DeviceConfig::Timer { range } => {
+                    bus.plug_in(*range, Arc::new(Mutex::new(Timer::new())))
+                }
+                DeviceConfig::Rtc { range } => {
+                    let rtc = match seed {
+                        Some(seed) => Rtc::deterministic(seed, self.cpu.frequency),
+                        None => Rtc::new(),
+                    };
+                    bus.plug_in(*range, Arc::new(Mutex::new(rtc)))
+                }
+                DeviceConfig::Files { range, root } => {
+                    let root = self.base_dir.join(root);
+                    if !root.is_dir() {
+                        return Err(format!(
+                            "device #{}: {} is not a directory",
+                            i + 1,
+                            root.display()
+                        ));
+                    }
+                    if range_len < files::REGISTERS {
+                        return Err(format!(
+                            "device #{}: range must be at least {} bytes",
+                            i + 1,
+                            files::REGISTERS
+                        ));
+                    }
+                    let files = Files::new(root, start);
+                    bus.plug_in(*range, Arc::new(Mutex::new(files)))
+                }
+                DeviceConfig::Framebuffer {
+                    range,
+                    size: (width, height),
+                    render,
+                    snapshot,
+                } => {
+                    let pixels = width.checked_mul(*height).ok_or_else(|| {
+                        format!("device #{}: {}x{} pixels are too many", i + 1, width, height)
+                    })?;
+                    if pixels == 0 || (range_len != pixels && range_len != pixels + 1) {
+                        return Err(format!(
+                            "device #{}: range must be {} bytes for {}x{} pixels, or one more for the snapshot register",
+                            i + 1,
+                            pixels,
+                            width,
+                            height
+                        ));
+                    }
+                    let mut fb = Framebuffer::new(*width, *height);
+                    let render = match render {
+                        FramebufferRender::HalfBlocks => Some(framebuffer::Render::HalfBlocks),
+                        FramebufferRender::Blocks => Some(framebuffer::Render::Blocks),
+                        FramebufferRender::None => None,
+                    };
+                    if let Some(render) = render {
+                        let out = Box::new(terminal.output());
+                        fb = fb.with_terminal(render, out, self.cpu.frequency);
+                    }
+                    if let Some(path) = snapshot {
+                        fb = fb.with_snapshot(self.base_dir.join(path));
+                    }
+                    bus.plug_in(*range, Arc::new(Mutex::new(fb)))
+                }
+                DeviceConfig::Psg {
+                    range,
+                    output,
+                    clock,
+                    sample_rate,
+                } => {
+                    if !clock.is_finite() || *clock <= 0.0 || *clock > MAX_PSG_CLOCK {
+                        return Err(format!(
+                            "device #{}: clock must be positive and at most {} MHz",
+                            i + 1,
+                            MAX_PSG_CLOCK
+                        ));
+                    }
+                    let cpu_hz = self.cpu.frequency as f64 * 1_000_000.0;
+                    if *sample_rate == 0 || *sample_rate as f64 > cpu_hz {
+                        return Err(format!(
+                            "device #{}: sample rate must be between 1 and the CPU frequency",
+                            i + 1
+                        ));
+                    }
+                    let psg = Psg::new(self.cpu.frequency, *clock, *sample_rate);
+                    let sound = Sound::new(psg, self.base_dir.join(output))
+                        .map_err(|err| format!("device #{}: {}", i + 1, err))?;
+                    bus.plug_in(*range, Arc::new(Mutex::new(sound)))
+                }
+                DeviceConfig::Exit { range } => {
+                    let exit = Exit::new(exit.clone());
+                    bus.plug_in(*range, Arc::new(Mutex::new(exit)))
+                }
+            }
+            .map_err(|err| format!("device #{}: {}", i + 1, err))?;
+        }
+
+        Ok(bus)
+    }
+}
+
+#[cfg(test)]
+mod machine_test {
+    use super::*;
+
+    #[test]
+    fn default_machine() {
+        let machine = Machine::parse(DEFAULT_MACHINE).unwrap();
+        assert_eq!(machine.devices.len(), 3);
+        assert!(machine
+            .build_bus(&Terminal::new(), &ExitStatus::default(), 0, None)
+            .is_ok());
+    }
+
+    #[test]
+    fn cpu_frequency() {
+        for frequency in ["0.0", "-1.0", "nan", "inf"] {
+            let config = format!("[cpu]\nfrequency = {}\n", frequency);
+            let err = Machine::parse(&config).err().unwrap();
+            assert!(err.starts_with("cpu: frequency"), "{}", err);
+        }
+        assert!(Machine::parse("[cpu]\nfrequency = 0.5\n").is_ok());
+    }
+
+    #[test]
+    fn files_range() {
+        let config = |end| {
+            format!(
+                "[[device]]\ntype = \"files\"\nrange = [0xFF00, {}]\nroot = \".\"\n",
+                end
+            )
+        };
+        let build = |config: &str| {
+            Machine::parse(config).unwrap().build_bus(
+                &Terminal::new(),
+                &ExitStatus::default(),
+                0,
+                None,
+            )
+        };
+        assert!(build(&config(0xFF0D)).is_ok());
+        let err = build(&config(0xFF0C)).err().unwrap();
+        assert!(err.starts_with("device #1"), "{}", err);
+    }
+
+    #[test]
+    fn overlapping_devices() {
+        let machine = Machine::parse(
+            r#"
+            [[device]]
+            type = "ram"
+            range = [0x0000, 0x7FFF]
+
+            [[device]]
+            type = "ram"
+            range = [0x7000, 0x8FFF]
+            "#,
+        )
+        .unwrap();
+
+        let err = machine
+            .build_bus(&Terminal::new(), &ExitStatus::default(), 0, None)
+            .err()
+            .unwrap();
+        assert!(err.starts_with("device #2"), "{}", err);
+    }
+
+    #[test]
+    fn framebuffer_size_overflow() {
+        let machine = Machine::parse(
+            r#"
+            [[device]]
+            type = "framebuffer"
+            range = [0x0000, 0x0FFF]
+            size = [0x1_0000_0000, 0x1_0000_0000]
+            "#,
+        )
+        .unwrap();
+
+        let err = machine
+            .build_bus(&Terminal::new(), &ExitStatus::default(), 0, None)
+            .err()
+            .unwrap();
+        assert!(err.starts_with("device #1"), "{}", err);
+    }
+
+    #[test]
+    fn psg_clock() {
+        for clock in ["0.0", "-1.0", "nan", "inf", "1e9"] {
+            let config = format!(
+                "[[device]]\ntype = \"psg\"\nrange = [0xFF00, 0xFF00]\noutput = \"psg.wav\"\nclock = {}\n",
+                clock
+            );
+            let err = Machine::parse(&config)
+                .unwrap()
+                .build_bus(&Terminal::new(), &ExitStatus::default(), 0, None)
+                .err()
+                .unwrap();
+            assert!(err.starts_with("device #1: clock"), "{}", err);
+        }
+    }
+}
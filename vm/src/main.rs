@@ -1,111 +1,302 @@
+mod console;
+mod exit;
+mod files;
+mod framebuffer;
+mod keyboard;
+mod lcd;
+mod loader;
+mod machine;
+mod parse;
+mod png;
+mod rtc;
+mod serial;
+mod sound;
 mod stdout;
+mod terminal;
+mod timer;
+mod wav;
 
 use clap::{arg, Command};
-use std::cell::RefCell;
-use std::fs::File;
-use std::io;
-use std::io::Read;
-use std::rc::Rc;
+use std::fmt::Display;
+use std::process;
+use std::thread;
 
-use mos6502::bus::Bus;
+use exit::ExitStatus;
+use loader::ImageFile;
+use machine::{CpuVariant, Machine, DEFAULT_MACHINE};
+use mos6502::bus::{SyncBus, Watchpoint};
 use mos6502::clock::Oscillator;
 use mos6502::cpu::{print_state, CPU};
-use mos6502::mem::{Memory, Ram};
+use mos6502::opcodes::OP_BRK;
 use mos6502::types::*;
-use stdout::Stdout;
+use terminal::Terminal;
 
-const CPU_FREQ_MHZ: f32 = 1.0;
+const ROM_START: Word = 0x0300;
 
 struct Opts {
-    program: String,
+    program: Option<String>,
+    machine: Option<String>,
+    load: Vec<(String, Option<Word>, Option<Byte>)>,
+    entry: Option<Word>,
+    fill: Byte,
+    seed: Option<u64>,
+    halt_on_brk: bool,
+    halt_on_loop: bool,
+    max_cycles: Option<u64>,
     debug: bool,
+    trace: bool,
+    watchpoints: Vec<Watchpoint>,
 }
 
 fn parse_cli_args() -> Opts {
     let args = Command::new("vm")
         .args(&[
-            arg!(<program> "Path to the program executable"),
+            arg!([program] "Path to the program executable, loaded at 0x0300"),
+            arg!(-m --machine <FILE> "Machine configuration in TOML").required(false),
+            arg!(-l --load <SPEC> ... "Load a file, raw binaries need an address, e.g. vectors.bin@FFFA")
+                .required(false),
+            arg!(-e --entry <ADDR> "Start at the given address instead of the reset vector")
+                .required(false),
+            arg!(-f --fill <BYTE> "Fill RAM with the given byte before loading").required(false),
+            arg!(--seed <N> "Seed the random numbers and start the clock at 2000-01-01")
+                .required(false),
+            arg!(--"halt-on-brk" "Stop when a BRK instruction is executed"),
+            arg!(--"halt-on-loop" "Stop when an instruction jumps to itself"),
+            arg!(--"max-cycles" <N> "Stop after the given number of cycles").required(false),
             arg!(-d --debug ... "Print CPU state on each tick"),
+            arg!(-t --trace "Print every bus access to stderr"),
+            arg!(-w --watch <SPEC> ... "Stop when memory is accessed, e.g. 0200-02FF:w=0A")
+                .required(false),
         ])
         .get_matches();
 
-    let program = args.value_of("program").unwrap();
+    let program = args.value_of("program").map(String::from);
+    let machine = args.value_of("machine").map(String::from);
+    let load = args
+        .values_of("load")
+        .unwrap_or_default()
+        .map(|spec| parse::parse_load(spec).unwrap_or_else(|err| exit_with_error(err)))
+        .collect();
+    let entry = args
+        .value_of("entry")
+        .map(|addr| parse::parse_word(addr).unwrap_or_else(|err| exit_with_error(err)));
+    let fill = args
+        .value_of("fill")
+        .map(|byte| parse::parse_byte(byte).unwrap_or_else(|err| exit_with_error(err)))
+        .unwrap_or(0);
+    let seed = args.value_of("seed").map(|n| {
+        n.parse()
+            .unwrap_or_else(|_| exit_with_error(format!("invalid seed: {}", n)))
+    });
+    let halt_on_brk = args.is_present("halt-on-brk");
+    let halt_on_loop = args.is_present("halt-on-loop");
+    let max_cycles = args.value_of("max-cycles").map(|n| {
+        n.parse()
+            .ok()
+            .filter(|&n| n > 0)
+            .unwrap_or_else(|| exit_with_error(format!("invalid number of cycles: {}", n)))
+    });
     let debug = args.is_present("debug");
+    let trace = args.is_present("trace");
+
+    let watchpoints = args
+        .values_of("watch")
+        .unwrap_or_default()
+        .map(|spec| parse::parse_watchpoint(spec).unwrap_or_else(|err| exit_with_error(err)))
+        .collect();
 
     Opts {
-        program: String::from(program),
+        program,
+        machine,
+        load,
+        entry,
+        fill,
+        seed,
+        halt_on_brk,
+        halt_on_loop,
+        max_cycles,
         debug,
+        trace,
+        watchpoints,
+    }
+}
+
+// Why the emulation has stopped.
+enum Halt {
+    Exit(Byte),
+    Break(Word),
+    Loop(Word),
+    CycleLimit(u64),
+    Watchpoint,
+    Interrupted,
+}
+
+impl Halt {
+    // Hitting the cycle limit means the program has not finished in time, which is a failure.
+    fn exit_code(&self) -> i32 {
+        match self {
+            Halt::Exit(code) => *code as i32,
+            Halt::CycleLimit(_) => 1,
+            Halt::Interrupted => 130, // like a shell reports a process killed by SIGINT
+            Halt::Break(_) | Halt::Loop(_) | Halt::Watchpoint => 0,
+        }
     }
 }
 
 struct VirtualMachine {
     cpu: CPU,
-    bus: Bus,
+    bus: SyncBus,
     clock: Oscillator,
+    exit: ExitStatus,
+    entry: Option<Word>,
+    halt_on_brk: bool,
+    halt_on_loop: bool,
+    max_cycles: Option<u64>,
     debug: bool,
 }
 
 impl VirtualMachine {
-    fn new() -> Self {
-        let mut bus = Bus::new();
-
-        let stdout = Stdout::new(Box::new(io::stdout()));
-        let out: Rc<RefCell<dyn Memory>> = Rc::new(RefCell::new(stdout));
-        bus.plug_in((0x0200, 0x02FF), Rc::clone(&out)).unwrap();
-
-        let mem: Rc<RefCell<dyn Memory>> = Rc::new(RefCell::new(Ram::new()));
-        bus.plug_in((0x0000, 0xFFFF), Rc::clone(&mem)).unwrap();
-
-        let clock = Oscillator::with_frequency(CPU_FREQ_MHZ);
-        let cpu = CPU::new();
+    fn new(
+        machine: &Machine,
+        terminal: &Terminal,
+        fill: Byte,
+        seed: Option<u64>,
+    ) -> Result<Self, String> {
+        let exit = ExitStatus::default();
+        let bus = machine.build_bus(terminal, &exit, fill, seed)?;
+        let clock = Oscillator::with_frequency(machine.cpu.frequency)?;
+        let cpu = match machine.cpu.variant {
+            CpuVariant::Nmos6502 => CPU::new(),
+        };
 
-        Self {
+        Ok(Self {
             cpu,
             bus,
             clock,
+            exit,
+            entry: None,
+            halt_on_brk: false,
+            halt_on_loop: false,
+            max_cycles: None,
             debug: false,
-        }
+        })
     }
 
-    fn load_rom_from_file(&mut self, filename: String) -> io::Result<()> {
-        let file = File::open(filename)?;
-        let mut addr: Word = 0x0300;
-
-        for byte in file.bytes() {
-            self.bus.write(addr, byte.unwrap());
-            addr = {
-                let (new_addr, wrapped) = addr.overflowing_add(1);
-                if wrapped {
-                    break;
-                }
-                new_addr
-            };
-        }
-
-        Ok(())
+    fn load_images(&mut self, files: &[ImageFile]) -> Result<(), String> {
+        loader::load_images(&mut self.bus, files)
     }
 
-    fn run_loop(&mut self) {
+    fn run_loop(&mut self) -> Halt {
         self.cpu.reset(&self.bus);
+        if let Some(entry) = self.entry {
+            self.cpu.set_pc(entry);
+        }
         let mut tick_count: u64 = 0;
         let mut real_tick;
 
         loop {
             self.clock.tick();
+            self.cpu.set_irq(self.bus.irq());
+            self.cpu.set_nmi(self.bus.nmi());
+            self.cpu.set_rdy(self.bus.rdy());
+            self.cpu.set_so(self.bus.so());
+            let pc = self.cpu.pc();
             real_tick = self.cpu.tick(&mut self.bus);
+            self.bus.tick(1);
             if self.debug && real_tick {
                 println!("--- tick {} ---", tick_count);
                 print_state(&self.cpu);
+                for (start, end) in self.bus.irq_sources() {
+                    println!("IRQ: 0x{:04X}-0x{:04X}", start, end);
+                }
+                for (start, end) in self.bus.nmi_sources() {
+                    println!("NMI: 0x{:04X}-0x{:04X}", start, end);
+                }
+            }
+            if let Some(access) = self.bus.take_watch_hit() {
+                println!("--- watchpoint hit ---");
+                println!("{}", access);
+                print_state(&self.cpu);
+                return Halt::Watchpoint;
+            }
+            if let Some(code) = self.exit.get() {
+                return Halt::Exit(code);
+            }
+            if terminal::interrupted() {
+                return Halt::Interrupted;
+            }
+            if real_tick {
+                match self.cpu.last_opcode() {
+                    Some(OP_BRK) if self.halt_on_brk => return Halt::Break(pc),
+                    Some(_) if self.halt_on_loop && self.cpu.pc() == pc => return Halt::Loop(pc),
+                    _ => {}
+                }
             }
             tick_count += 1;
+            if self.max_cycles == Some(tick_count) {
+                return Halt::CycleLimit(tick_count);
+            }
         }
     }
 }
 
+fn exit_with_error(err: impl Display) -> ! {
+    eprintln!("error: {}", err);
+    process::exit(2);
+}
+
 fn main() {
     let opts = parse_cli_args();
-    let mut vm = VirtualMachine::new();
+
+    let machine = match &opts.machine {
+        Some(path) => Machine::from_file(path),
+        None => Machine::parse(DEFAULT_MACHINE),
+    }
+    .unwrap_or_else(|err| exit_with_error(err));
+
+    let terminal = Terminal::new();
+    let mut vm = VirtualMachine::new(&machine, &terminal, opts.fill, opts.seed)
+        .unwrap_or_else(|err| exit_with_error(err));
+    vm.entry = opts.entry;
+    vm.halt_on_brk = opts.halt_on_brk;
+    vm.halt_on_loop = opts.halt_on_loop;
+    vm.max_cycles = opts.max_cycles;
     vm.debug = opts.debug;
-    vm.load_rom_from_file(opts.program).unwrap();
-    vm.run_loop();
+    if opts.trace {
+        vm.bus.add_hook(|access| eprintln!("{}", access));
+    }
+    for watchpoint in opts.watchpoints {
+        vm.bus.add_watchpoint(watchpoint);
+    }
+
+    let files: Vec<ImageFile> = opts
+        .program
+        .iter()
+        .map(|path| (path.clone(), Some(ROM_START), None))
+        .chain(opts.load)
+        .map(|(path, addr, zero)| {
+            ImageFile::open(&path, addr, zero).unwrap_or_else(|err| exit_with_error(err))
+        })
+        .collect();
+    vm.load_images(&files)
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    // The entry point given on the command line wins over the one found in the files.
+    if vm.entry.is_none() {
+        vm.entry = files.iter().find_map(|file| file.image.entry);
+    }
+
+    // The CPU runs in the background, while the main thread takes care of the terminal.
+    terminal::catch_interrupt();
+    let emulation = thread::spawn(move || vm.run_loop());
+    terminal.run().unwrap();
+
+    let halt = emulation.join().unwrap();
+    match halt {
+        Halt::Break(addr) if opts.debug => println!("--- BRK at 0x{:04X} ---", addr),
+        Halt::Loop(addr) if opts.debug => println!("--- jump to self at 0x{:04X} ---", addr),
+        Halt::CycleLimit(cycles) => eprintln!("stopped after {} cycles", cycles),
+        _ => {}
+    }
+    process::exit(halt.exit_code());
 }
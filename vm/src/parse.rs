@@ -0,0 +1,159 @@
+use mos6502::bus::{AccessKind, Watchpoint};
+use mos6502::types::*;
+
+// Parses an address or a byte written in hex, with an optional `0x` or `$` prefix.
+pub fn parse_hex(s: &str) -> Result<u32, String> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix('$'))
+        .unwrap_or(s);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("invalid hex number: {}", s))
+}
+
+pub fn parse_word(s: &str) -> Result<Word, String> {
+    let value = parse_hex(s)?;
+    Word::try_from(value).map_err(|_| format!("address out of range: {}", s))
+}
+
+pub fn parse_byte(s: &str) -> Result<Byte, String> {
+    let value = parse_hex(s)?;
+    Byte::try_from(value).map_err(|_| format!("byte out of range: {}", s))
+}
+
+// Parses either a single address or an inclusive range written as `START-END`.
+pub fn parse_range(s: &str) -> Result<MemRange, String> {
+    let range = match s.split_once('-') {
+        Some((start, end)) => (parse_word(start)?, parse_word(end)?),
+        None => {
+            let addr = parse_word(s)?;
+            (addr, addr)
+        }
+    };
+    if range.0 > range.1 {
+        return Err(format!("invalid range: {}", s));
+    }
+    Ok(range)
+}
+
+// Parses a watchpoint in the form of `RANGE[:r|w][=VALUE]`, e.g. `0200-02FF:w=0A`.
+pub fn parse_watchpoint(s: &str) -> Result<Watchpoint, String> {
+    let (s, value) = match s.split_once('=') {
+        Some((s, value)) => (s, Some(parse_byte(value)?)),
+        None => (s, None),
+    };
+
+    let (range, kind) = match s.split_once(':') {
+        Some((range, "r")) => (range, Some(AccessKind::Read)),
+        Some((range, "w")) => (range, Some(AccessKind::Write)),
+        Some((range, "rw")) => (range, None),
+        Some((_, kind)) => return Err(format!("invalid access kind: {}", kind)),
+        None => (s, None),
+    };
+
+    let mut watchpoint = Watchpoint::new(parse_range(range)?);
+    if let Some(kind) = kind {
+        watchpoint = watchpoint.on(kind);
+    }
+    if let Some(value) = value {
+        watchpoint = watchpoint.with_value(value);
+    }
+    Ok(watchpoint)
+}
+
+// Parses a file to be loaded, written as `FILE[@ADDR[,ZP]]`.
+pub fn parse_load(s: &str) -> Result<(String, Option<Word>, Option<Byte>), String> {
+    match s.rsplit_once('@') {
+        Some((path, place)) if !path.is_empty() => {
+            let (addr, zero) = match place.split_once(',') {
+                Some((addr, zero)) => (parse_word(addr)?, Some(parse_byte(zero)?)),
+                None => (parse_word(place)?, None),
+            };
+            Ok((path.to_string(), Some(addr), zero))
+        }
+        Some(_) => Err(format!("expected FILE[@ADDR[,ZP]]: {}", s)),
+        None => Ok((s.to_string(), None, None)),
+    }
+}
+
+#[cfg(test)]
+mod parse_test {
+    use super::*;
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_hex("0x1F"), Ok(0x1F));
+        assert_eq!(parse_hex("$c000"), Ok(0xC000));
+        assert_eq!(parse_hex("FFFA"), Ok(0xFFFA));
+        assert!(parse_hex("12G4").is_err());
+        assert!(parse_hex("").is_err());
+        assert!(parse_word("10000").is_err());
+        assert!(parse_byte("100").is_err());
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(parse_range("0200-02FF"), Ok((0x0200, 0x02FF)));
+        assert_eq!(parse_range("$FFFC"), Ok((0xFFFC, 0xFFFC)));
+        assert_eq!(parse_range("0400-0400"), Ok((0x0400, 0x0400)));
+        assert!(parse_range("02FF-0200").is_err());
+        assert!(parse_range("0200-").is_err());
+        assert!(parse_range("0200-XYZ").is_err());
+    }
+
+    #[test]
+    fn watchpoint() {
+        let wp = parse_watchpoint("0200-02FF:w=0A").unwrap();
+        assert_eq!(wp.range, (0x0200, 0x02FF));
+        assert_eq!(wp.kind, Some(AccessKind::Write));
+        assert_eq!(wp.value, Some(0x0A));
+
+        let wp = parse_watchpoint("FFFE:r").unwrap();
+        assert_eq!(wp.range, (0xFFFE, 0xFFFE));
+        assert_eq!(wp.kind, Some(AccessKind::Read));
+        assert_eq!(wp.value, None);
+
+        let wp = parse_watchpoint("0010:rw").unwrap();
+        assert_eq!(wp.kind, None);
+
+        let wp = parse_watchpoint("0010=FF").unwrap();
+        assert_eq!(wp.range, (0x0010, 0x0010));
+        assert_eq!(wp.kind, None);
+        assert_eq!(wp.value, Some(0xFF));
+    }
+
+    #[test]
+    fn bad_watchpoint() {
+        assert!(parse_watchpoint("02FF-0200:w").is_err());
+        assert!(parse_watchpoint("0200:x").is_err());
+        assert!(parse_watchpoint("0200:").is_err());
+        assert!(parse_watchpoint("02G0:w").is_err());
+        assert!(parse_watchpoint("0200:w=100").is_err());
+        assert!(parse_watchpoint("0200:w=").is_err());
+    }
+
+    #[test]
+    fn load() {
+        assert_eq!(
+            parse_load("prog.bin"),
+            Ok(("prog.bin".to_string(), None, None))
+        );
+        assert_eq!(
+            parse_load("prog.bin@C000"),
+            Ok(("prog.bin".to_string(), Some(0xC000), None))
+        );
+        assert_eq!(
+            parse_load("prog.o65@C000,80"),
+            Ok(("prog.o65".to_string(), Some(0xC000), Some(0x80)))
+        );
+        // only the last @ starts the address
+        assert_eq!(
+            parse_load("a@b.bin@0x0200"),
+            Ok(("a@b.bin".to_string(), Some(0x0200), None))
+        );
+        assert!(parse_load("prog.o65@C000,").is_err());
+        assert!(parse_load("prog.o65@C000,100").is_err());
+        assert!(parse_load("@C000").is_err());
+        assert!(parse_load("prog.bin@").is_err());
+        assert!(parse_load("prog.bin@10000").is_err());
+    }
+}
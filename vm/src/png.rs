@@ -0,0 +1,131 @@
+// Minimal PNG encoder for palette images. The pixel data is stored in uncompressed deflate
+// blocks, which makes the files larger than they could be, but keeps the encoder tiny and
+// its output the same byte for byte on every platform.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+const COLOR_TYPE_PALETTE: u8 = 3;
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// Encodes an image with one byte per pixel, each being an index into the palette.
+pub fn encode(width: usize, height: usize, palette: &[[u8; 3]], pixels: &[u8]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height);
+    assert!(!palette.is_empty() && palette.len() <= 256);
+
+    let mut png = SIGNATURE.to_vec();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, COLOR_TYPE_PALETTE, 0, 0, 0]); // depth, type, methods
+    write_chunk(&mut png, b"IHDR", &header);
+
+    write_chunk(&mut png, b"PLTE", &palette.concat());
+
+    // Every scanline starts with the filter type, which is always 0 (none).
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width.max(1)) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01]; // deflate with 32K window, no preset dictionary
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod png_test {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn image() {
+        let palette = [[0, 0, 0], [0xFF, 0xFF, 0xFF]];
+        let png = encode(2, 2, &palette, &[0, 1, 1, 0]);
+
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(png[12..16], *b"IHDR");
+        assert_eq!(png[16..20], 2_u32.to_be_bytes());
+        assert_eq!(png[20..24], 2_u32.to_be_bytes());
+        assert_eq!(png[24..29], [8, COLOR_TYPE_PALETTE, 0, 0, 0]);
+        assert_eq!(
+            png[png.len() - 12..],
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+
+        // the scanlines are stored as is, each prefixed with the filter type
+        let idat = png.windows(4).position(|w| w == b"IDAT").unwrap() + 4;
+        assert_eq!(png[idat + 7..idat + 13], [0, 0, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn large_image() {
+        let pixels = vec![0; 300 * 300];
+        let data = zlib_stored(&pixels);
+        // two stored blocks, only the second of which is final
+        assert_eq!(
+            data.len(),
+            2 + 5 + MAX_STORED_BLOCK + 5 + (90000 - MAX_STORED_BLOCK) + 4
+        );
+        assert_eq!(data[2], 0);
+        assert_eq!(data[2 + 5 + MAX_STORED_BLOCK], 1);
+    }
+}
@@ -0,0 +1,219 @@
+use mos6502::mem::{Device, Memory};
+use mos6502::types::*;
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const REG_SECONDS: Word = 0x00; // reading it latches the whole date and time
+const REG_WEEKDAY: Word = 0x07;
+const REG_CONTROL: Word = 0x08;
+const REG_RANDOM: Word = 0x09;
+
+const CONTROL_BCD: Byte = 0b0000_0001;
+
+const Y2K: i64 = 946684800; // 2000-01-01 00:00:00 UTC
+
+// Where the time comes from: the wall clock of the host, or the emulated time
+// counted in CPU cycles since midnight of January 1, 2000.
+enum Clock {
+    Host,
+    Emulated { cycles: u64, frequency: f64 },
+}
+
+// Real-time clock with a random number generator. The registers hold seconds, minutes, hours,
+// day, month, year, century and day of the week (0 is Sunday), in binary or BCD. Reading the
+// seconds latches all of them, so the time cannot roll over while the program reads it.
+// Every read of the random register returns the next byte of a pseudo-random sequence.
+pub struct Rtc {
+    clock: Clock,
+    control: Byte,
+    latch: Cell<[Byte; 8]>,
+    rng: Cell<u64>,
+}
+
+impl Rtc {
+    // Shows the local time of the host, and the random numbers are different every time.
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::with_clock(Clock::Host, seed ^ std::process::id() as u64)
+    }
+
+    // Both the time and the random numbers depend only on the seed and on the number
+    // of cycles the program has run, so the runs can be repeated exactly.
+    pub fn deterministic(seed: u64, cpu_frequency: f32) -> Self {
+        let clock = Clock::Emulated {
+            cycles: 0,
+            frequency: cpu_frequency as f64 * 1_000_000.0,
+        };
+        Self::with_clock(clock, seed)
+    }
+
+    fn with_clock(clock: Clock, seed: u64) -> Self {
+        Self {
+            clock,
+            control: 0,
+            latch: Cell::new([0; 8]),
+            rng: Cell::new(seed),
+        }
+    }
+
+    fn now(&self) -> libc::tm {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        match self.clock {
+            Clock::Host => {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as libc::time_t)
+                    .unwrap_or(0);
+                unsafe { libc::localtime_r(&time, &mut tm) };
+            }
+            Clock::Emulated { cycles, frequency } => {
+                let time = (Y2K + (cycles as f64 / frequency) as i64) as libc::time_t;
+                unsafe { libc::gmtime_r(&time, &mut tm) };
+            }
+        }
+        tm
+    }
+
+    fn latch(&self) {
+        let tm = self.now();
+        let year = tm.tm_year + 1900;
+        self.latch.set([
+            tm.tm_sec.min(59) as Byte, // no leap seconds
+            tm.tm_min as Byte,
+            tm.tm_hour as Byte,
+            tm.tm_mday as Byte,
+            (tm.tm_mon + 1) as Byte,
+            (year % 100) as Byte,
+            (year / 100) as Byte,
+            tm.tm_wday as Byte,
+        ]);
+    }
+
+    // SplitMix64, which gives a good sequence for any seed, including zero.
+    fn random(&self) -> Byte {
+        let state = self.rng.get().wrapping_add(0x9E3779B97F4A7C15);
+        self.rng.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        ((z ^ (z >> 31)) >> 56) as Byte
+    }
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn to_bcd(value: Byte) -> Byte {
+    ((value / 10) << 4) | (value % 10)
+}
+
+impl Memory for Rtc {
+    fn read(&self, addr: Word) -> Byte {
+        match addr {
+            REG_SECONDS..=REG_WEEKDAY => {
+                if addr == REG_SECONDS {
+                    self.latch();
+                }
+                let value = self.latch.get()[addr as usize];
+                if self.control & CONTROL_BCD != 0 {
+                    to_bcd(value)
+                } else {
+                    value
+                }
+            }
+            REG_CONTROL => self.control,
+            REG_RANDOM => self.random(),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        if addr == REG_CONTROL {
+            self.control = data;
+        }
+    }
+}
+
+impl Device for Rtc {
+    fn tick(&mut self, cycles: u64) {
+        if let Clock::Emulated {
+            cycles: elapsed, ..
+        } = &mut self.clock
+        {
+            *elapsed += cycles;
+        }
+    }
+}
+
+#[cfg(test)]
+mod rtc_test {
+    use super::*;
+
+    fn read_all(rtc: &Rtc) -> Vec<Byte> {
+        (REG_SECONDS..=REG_WEEKDAY)
+            .map(|reg| rtc.read(reg))
+            .collect()
+    }
+
+    #[test]
+    fn emulated_time() {
+        let mut rtc = Rtc::deterministic(0, 1.0);
+        // 2000-01-01 was a Saturday
+        assert_eq!(read_all(&rtc), vec![0, 0, 0, 1, 1, 0, 20, 6]);
+
+        rtc.tick(1_000_000 * (86400 + 3600 + 61) + 999_999);
+        assert_eq!(read_all(&rtc), vec![1, 1, 1, 2, 1, 0, 20, 0]);
+    }
+
+    #[test]
+    fn bcd() {
+        let mut rtc = Rtc::deterministic(0, 2.0);
+        rtc.write(REG_CONTROL, CONTROL_BCD);
+        rtc.tick(2_000_000 * (59 + 60 * 34 + 3600 * 23));
+        assert_eq!(
+            read_all(&rtc),
+            vec![0x59, 0x34, 0x23, 0x01, 0x01, 0x00, 0x20, 0x06]
+        );
+    }
+
+    #[test]
+    fn latch_on_read() {
+        let mut rtc = Rtc::deterministic(0, 1.0);
+        rtc.tick(1_000_000 * 59);
+        assert_eq!(rtc.read(REG_SECONDS), 59);
+
+        // the minutes still belong to the moment the seconds were read
+        rtc.tick(1_000_000);
+        assert_eq!(rtc.read(REG_SECONDS + 1), 0);
+        assert_eq!(rtc.read(REG_SECONDS), 0);
+        assert_eq!(rtc.read(REG_SECONDS + 1), 1);
+    }
+
+    #[test]
+    fn host_time() {
+        let rtc = Rtc::new();
+        let time = read_all(&rtc);
+        assert!(time[0] < 60 && time[1] < 60 && time[2] < 24);
+        assert!((1..=31).contains(&time[3]) && (1..=12).contains(&time[4]));
+        assert!(time[6] >= 20 && time[7] < 7);
+    }
+
+    #[test]
+    fn seeded_random() {
+        let sequence = |seed| {
+            let rtc = Rtc::deterministic(seed, 1.0);
+            (0..16).map(|_| rtc.read(REG_RANDOM)).collect::<Vec<_>>()
+        };
+        assert_eq!(sequence(42), sequence(42));
+        assert_ne!(sequence(42), sequence(43));
+
+        let bytes = sequence(0);
+        assert!(bytes.iter().any(|&b| b != bytes[0]));
+    }
+}
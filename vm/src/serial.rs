@@ -0,0 +1,168 @@
+use mos6502::acia::Serial;
+use mos6502::types::*;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::FromRawFd;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::terminal::Terminal;
+
+// Serial line connected to the host. Received bytes come from a channel filled by a background
+// thread, and transmitted bytes go to a writer that never blocks the emulation.
+pub struct Link {
+    rx: Receiver<Byte>,
+    tx: Box<dyn Write + Send>,
+}
+
+impl Serial for Link {
+    fn receive(&mut self) -> Option<Byte> {
+        self.rx.try_recv().ok()
+    }
+
+    // Nobody may be listening on the other end, in which case the byte is lost,
+    // just like with a real cable that is not plugged in.
+    fn transmit(&mut self, data: Byte) {
+        let _ = self.tx.write_all(&[data]);
+    }
+}
+
+// Writer that hands the bytes over to a background thread.
+struct Pipe(Sender<Byte>);
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            self.0
+                .send(b)
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Reads the stream byte by byte until it is closed.
+fn forward(mut reader: impl Read, tx: &Sender<Byte>) {
+    let mut buf = [0; 256];
+    while let Ok(n @ 1..) = reader.read(&mut buf) {
+        if buf[..n].iter().any(|&b| tx.send(b).is_err()) {
+            return;
+        }
+    }
+}
+
+pub fn terminal(terminal: &Terminal) -> Link {
+    Link {
+        rx: terminal.input(),
+        tx: Box::new(terminal.output()),
+    }
+}
+
+// Creates a pseudo-terminal and returns the path of its slave side, which can be opened
+// with any terminal program, e.g. `screen /dev/pts/3` or `minicom -p /dev/pts/3`.
+pub fn pty() -> Result<(Link, String), String> {
+    let err = |what: &str| format!("{}: {}", what, io::Error::last_os_error());
+
+    let (master, path) = unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return Err(err("failed to open pty"));
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(err("failed to unlock pty"));
+        }
+
+        // The line must pass the bytes through as they are, like a serial cable does.
+        let mut termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) == 0 {
+            libc::cfmakeraw(&mut termios);
+            libc::tcsetattr(fd, libc::TCSANOW, &termios);
+        }
+
+        let mut name = [0 as libc::c_char; 64];
+        if libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0 {
+            return Err(err("failed to get pty name"));
+        }
+        let path = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+        (master, path)
+    };
+
+    // Keep the slave side open, so reading the master does not fail while no program
+    // is attached to the pty, and one can come and go as it pleases.
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .map_err(|err| format!("failed to open {}: {}", path, err))?;
+
+    let reader = master
+        .try_clone()
+        .map_err(|err| format!("failed to open pty: {}", err))?;
+    let (rx_tx, rx) = channel();
+    thread::spawn(move || {
+        let _slave = slave;
+        forward(reader, &rx_tx);
+    });
+
+    let (tx, tx_rx) = channel::<Byte>();
+    thread::spawn(move || {
+        let mut master = master;
+        for b in tx_rx {
+            if master.write_all(&[b]).is_err() {
+                return;
+            }
+        }
+    });
+
+    let link = Link {
+        rx,
+        tx: Box::new(Pipe(tx)),
+    };
+    Ok((link, path))
+}
+
+// Listens on the given port on localhost and talks to one client at a time,
+// e.g. `telnet localhost 6551` or `nc localhost 6551`.
+pub fn tcp(port: u16) -> Result<Link, String> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|err| format!("failed to listen on port {}: {}", port, err))?;
+
+    let client: Arc<Mutex<Option<TcpStream>>> = Arc::new(Mutex::new(None));
+    let (rx_tx, rx) = channel();
+    let accepted = client.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_nodelay(true);
+            if let Ok(writer) = stream.try_clone() {
+                *accepted.lock().unwrap() = Some(writer);
+            }
+            forward(&stream, &rx_tx);
+            *accepted.lock().unwrap() = None;
+        }
+    });
+
+    let (tx, tx_rx) = channel::<Byte>();
+    thread::spawn(move || {
+        for b in tx_rx {
+            let mut client = client.lock().unwrap();
+            if let Some(stream) = client.as_mut() {
+                if stream.write_all(&[b]).is_err() {
+                    *client = None;
+                }
+            }
+        }
+    });
+
+    Ok(Link {
+        rx,
+        tx: Box::new(Pipe(tx)),
+    })
+}
@@ -0,0 +1,112 @@
+use mos6502::mem::{Device, Memory};
+use mos6502::psg::Psg;
+use mos6502::types::*;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use crate::wav;
+
+const CHUNK: usize = 4096; // samples kept in memory before they are written out
+
+// Sound generator recorded into a WAV file. The samples are written as they are generated,
+// and the file is completed when the VM stops.
+pub struct Sound {
+    psg: Psg,
+    output: PathBuf,
+    writer: Option<wav::Writer<BufWriter<File>>>,
+}
+
+impl Sound {
+    pub fn new(psg: Psg, output: PathBuf) -> Result<Self, String> {
+        let writer = File::create(&output)
+            .and_then(|file| wav::Writer::new(BufWriter::new(file), psg.sample_rate()))
+            .map_err(|err| format!("failed to create {}: {}", output.display(), err))?;
+        Ok(Self {
+            psg,
+            output,
+            writer: Some(writer),
+        })
+    }
+
+    // A recording that cannot be written is given up, the emulation goes on.
+    fn save(&mut self) {
+        let samples = self.psg.take_samples();
+        if let Some(writer) = &mut self.writer {
+            if let Err(err) = writer.write(&samples) {
+                eprintln!("failed to save {}: {}", self.output.display(), err);
+                self.writer = None;
+            }
+        }
+    }
+}
+
+impl Memory for Sound {
+    fn read(&self, addr: Word) -> Byte {
+        self.psg.read(addr)
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        self.psg.write(addr, data);
+    }
+}
+
+impl Device for Sound {
+    fn tick(&mut self, cycles: u64) {
+        self.psg.tick(cycles);
+        if self.psg.samples().len() >= CHUNK {
+            self.save();
+        }
+    }
+
+    // Loading an image over the port must not play anything.
+    fn poke(&mut self, _addr: Word, _data: Byte) {}
+}
+
+// The VM drops the devices when it stops, so this is where the recording is completed.
+impl Drop for Sound {
+    fn drop(&mut self) {
+        self.save();
+        if let Some(writer) = self.writer.take() {
+            if let Err(err) = writer.finish() {
+                eprintln!("failed to save {}: {}", self.output.display(), err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod sound_test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn recording() {
+        let path = std::env::temp_dir().join(format!("sound-{}.wav", std::process::id()));
+        let mut sound = Sound::new(Psg::new(1.0, 4.0, 8000), path.clone()).unwrap();
+        sound.write(0, 0x8F); // channel 0, period 0x00F
+        sound.write(0, 0x00);
+        sound.write(0, 0x90); // full volume
+        sound.poke(0, 0x9F);
+        sound.tick(1000);
+        drop(sound);
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wav.len(), 44 + 8 * 2);
+        assert!(wav[44..].chunks(2).all(|s| s != [0, 0]));
+    }
+
+    #[test]
+    fn streaming() {
+        let path = std::env::temp_dir().join(format!("sound-stream-{}.wav", std::process::id()));
+        let mut sound = Sound::new(Psg::new(1.0, 4.0, 1_000_000), path.clone()).unwrap();
+        sound.tick(CHUNK as u64 + 10);
+        assert!(sound.psg.samples().is_empty());
+        drop(sound);
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(wav[40..44], ((CHUNK as u32 + 10) * 2).to_le_bytes());
+    }
+}
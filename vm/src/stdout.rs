@@ -1,14 +1,14 @@
-use mos6502::mem::Memory;
+use mos6502::mem::{Device, Memory};
 use mos6502::types::*;
 use std::io;
 
 pub struct Stdout {
-    out: Box<dyn io::Write>,
+    out: Box<dyn io::Write + Send>,
     buf: Vec<u8>,
 }
 
 impl Stdout {
-    pub fn new(out: Box<dyn io::Write>) -> Self {
+    pub fn new(out: Box<dyn io::Write + Send>) -> Self {
         Self {
             out,
             buf: Vec::with_capacity(255),
@@ -24,9 +24,11 @@ impl Memory for Stdout {
     fn write(&mut self, addr: Word, data: Byte) {
         self.buf.push(data);
         if addr == 0xFF {
-            self.out.write(self.buf.as_slice()).unwrap();
+            self.out.write_all(self.buf.as_slice()).unwrap();
             self.out.flush().unwrap();
             self.buf.clear();
         }
     }
 }
+
+impl Device for Stdout {}
@@ -0,0 +1,192 @@
+use std::cell::RefCell;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::OnceLock;
+use std::thread;
+
+// Terminal owns the host terminal and does all the I/O on behalf of the devices.
+// The devices live on the emulation thread together with the CPU and talk to it through channels,
+// so a slow terminal never stalls the emulation.
+pub struct Terminal {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    inputs: RefCell<Vec<Sender<u8>>>,
+}
+
+impl Terminal {
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        Self {
+            tx,
+            rx,
+            inputs: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn output(&self) -> Output {
+        Output {
+            tx: self.tx.clone(),
+        }
+    }
+
+    // Returns the channel that receives every byte read from stdin.
+    pub fn input(&self) -> Receiver<u8> {
+        let (tx, rx) = channel();
+        self.inputs.borrow_mut().push(tx);
+        rx
+    }
+
+    // Prints everything the devices send until all outputs are dropped. If any device reads
+    // the input, the terminal is switched to raw mode, so the keys are delivered as soon as
+    // they are pressed, and restored when the emulation is over.
+    pub fn run(self) -> io::Result<()> {
+        drop(self.tx);
+
+        let inputs = self.inputs.into_inner();
+        let _raw_mode = if inputs.is_empty() {
+            None
+        } else {
+            thread::spawn(move || read_input(inputs));
+            RawMode::enable()
+        };
+
+        let mut stdout = io::stdout();
+        for data in self.rx {
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn read_input(mut inputs: Vec<Sender<u8>>) {
+    let mut buf = [0; 64];
+    let mut stdin = io::stdin();
+    while let Ok(n @ 1..) = stdin.read(&mut buf) {
+        inputs.retain(|tx| buf[..n].iter().all(|&b| tx.send(b).is_ok()));
+        if inputs.is_empty() {
+            break;
+        }
+    }
+}
+
+// Writable end of the terminal that can be handed over to a device.
+pub struct Output {
+    tx: Sender<Vec<u8>>,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "terminal is closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Writer that keeps everything written to it, standing in for the terminal in the tests.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Buffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Buffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+
+    // Returns the text written since the last call.
+    pub fn take(&self) -> String {
+        String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Settings of the terminal before switching to raw mode. Kept globally,
+// so they can be restored when the VM is killed with Ctrl-C.
+static SAVED_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Makes Ctrl-C ask the emulation to stop, so that the devices are dropped and save their
+// output files as usual. Pressing it again kills the VM right away.
+pub fn catch_interrupt() {
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as *const () as libc::sighandler_t,
+        );
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+// Turns off line buffering and echo on stdin while it is alive. Output processing and signals
+// are left alone, so newlines are still printed properly and Ctrl-C stops the VM.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return None;
+            }
+
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return None;
+            }
+            SAVED_TERMIOS.get_or_init(|| termios);
+
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+            termios.c_iflag &= !(libc::ICRNL | libc::IXON);
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return None;
+            }
+        }
+        Some(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(saved) = SAVED_TERMIOS.get() {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            }
+        }
+    }
+}
+
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    if !INTERRUPTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    if let Some(saved) = SAVED_TERMIOS.get() {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+        }
+    }
+    unsafe { libc::_exit(130) };
+}
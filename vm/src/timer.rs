@@ -0,0 +1,215 @@
+use mos6502::mem::{Device, Memory};
+use mos6502::types::*;
+use std::cell::Cell;
+
+const REG_RELOAD_LO: Word = 0x00;
+const REG_RELOAD_HI: Word = 0x01;
+const REG_CONTROL: Word = 0x02;
+const REG_STATUS: Word = 0x03;
+const REG_COUNTER_LO: Word = 0x04;
+const REG_COUNTER_HI: Word = 0x05;
+
+const CONTROL_RUN: Byte = 0b0000_0001; // setting it starts the countdown from the reload value
+const CONTROL_PERIODIC: Byte = 0b0000_0010; // reload and keep counting after expiring
+const CONTROL_IRQ: Byte = 0b1000_0000; // raise IRQ when expired
+
+const STATUS_EXPIRED: Byte = 0b1000_0000; // cleared by reading the status
+
+// Interval timer counting CPU cycles. It counts down from the reload value, and when it runs out,
+// it sets the expired flag and optionally raises IRQ until the status is read. In the periodic
+// mode it starts over, so the period is the reload value in cycles (0 stands for 65536).
+pub struct Timer {
+    reload: Word,
+    counter: u32,
+    control: Byte,
+    expired: Cell<bool>,
+    latch: Cell<Byte>, // high byte of the counter, latched when reading the low one
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            reload: 0,
+            counter: 0,
+            control: 0,
+            expired: Cell::new(false),
+            latch: Cell::new(0),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        match self.reload {
+            0 => 0x10000,
+            n => n as u32,
+        }
+    }
+}
+
+impl Memory for Timer {
+    fn read(&self, addr: Word) -> Byte {
+        match addr {
+            REG_RELOAD_LO => self.reload as Byte,
+            REG_RELOAD_HI => (self.reload >> 8) as Byte,
+            REG_CONTROL => self.control,
+            REG_STATUS if self.expired.take() => STATUS_EXPIRED,
+            REG_COUNTER_LO => {
+                let counter = self.counter as Word;
+                self.latch.set((counter >> 8) as Byte);
+                counter as Byte
+            }
+            REG_COUNTER_HI => self.latch.get(),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: Word, data: Byte) {
+        match addr {
+            REG_RELOAD_LO => self.reload = (self.reload & 0xFF00) | data as Word,
+            REG_RELOAD_HI => self.reload = (self.reload & 0x00FF) | (data as Word) << 8,
+            REG_CONTROL => {
+                if data & !self.control & CONTROL_RUN != 0 {
+                    self.counter = self.period();
+                }
+                self.control = data;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Device for Timer {
+    fn tick(&mut self, cycles: u64) {
+        let mut cycles = cycles;
+        while self.control & CONTROL_RUN != 0 && cycles > 0 {
+            let step = cycles.min(self.counter as u64);
+            self.counter -= step as u32;
+            cycles -= step;
+
+            if self.counter == 0 {
+                self.expired.set(true);
+                if self.control & CONTROL_PERIODIC != 0 {
+                    self.counter = self.period();
+                } else {
+                    self.control &= !CONTROL_RUN;
+                }
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.control & CONTROL_IRQ != 0 && self.expired.get()
+    }
+}
+
+#[cfg(test)]
+mod timer_test {
+    use super::*;
+
+    fn start(timer: &mut Timer, reload: Word, control: Byte) {
+        timer.write(REG_RELOAD_LO, reload as Byte);
+        timer.write(REG_RELOAD_HI, (reload >> 8) as Byte);
+        timer.write(REG_CONTROL, control | CONTROL_RUN);
+    }
+
+    #[test]
+    fn one_shot() {
+        let mut timer = Timer::new();
+        start(&mut timer, 100, CONTROL_IRQ);
+
+        timer.tick(99);
+        assert!(!timer.irq());
+        assert_eq!(timer.read(REG_COUNTER_LO), 1);
+        timer.tick(1);
+        assert!(timer.irq());
+        assert_eq!(timer.read(REG_CONTROL) & CONTROL_RUN, 0);
+
+        assert_eq!(timer.read(REG_STATUS), STATUS_EXPIRED);
+        assert!(!timer.irq());
+        assert_eq!(timer.read(REG_STATUS), 0);
+
+        timer.tick(1000);
+        assert!(!timer.irq());
+    }
+
+    #[test]
+    fn periodic() {
+        let mut timer = Timer::new();
+        start(&mut timer, 1000, CONTROL_PERIODIC);
+
+        for _ in 0..3 {
+            timer.tick(999);
+            assert_eq!(timer.read(REG_STATUS), 0);
+            timer.tick(1);
+            assert_eq!(timer.read(REG_STATUS), STATUS_EXPIRED);
+        }
+
+        // expiring several times between the reads is not counted
+        timer.tick(2500);
+        assert_eq!(timer.read(REG_STATUS), STATUS_EXPIRED);
+        assert_eq!(timer.read(REG_COUNTER_LO), 0xF4); // 500
+        assert_eq!(timer.read(REG_COUNTER_HI), 0x01);
+        assert!(!timer.irq()); // not enabled
+    }
+
+    #[test]
+    fn zero_reload() {
+        let mut timer = Timer::new();
+        start(&mut timer, 0, 0);
+        timer.tick(0xFFFF);
+        assert_eq!(timer.read(REG_STATUS), 0);
+        timer.tick(1);
+        assert_eq!(timer.read(REG_STATUS), STATUS_EXPIRED);
+    }
+
+    #[test]
+    fn latched_counter() {
+        let mut timer = Timer::new();
+        start(&mut timer, 0x0105, 0);
+        timer.tick(4);
+        assert_eq!(timer.read(REG_COUNTER_LO), 0x01);
+        timer.tick(2);
+        assert_eq!(timer.read(REG_COUNTER_HI), 0x01);
+        assert_eq!(timer.read(REG_COUNTER_LO), 0xFF);
+        assert_eq!(timer.read(REG_COUNTER_HI), 0x00);
+    }
+
+    #[test]
+    fn change_mode() {
+        let mut timer = Timer::new();
+        start(&mut timer, 100, CONTROL_PERIODIC);
+        timer.tick(60);
+
+        // changing the other bits keeps the phase
+        timer.write(REG_CONTROL, CONTROL_RUN | CONTROL_PERIODIC | CONTROL_IRQ);
+        timer.tick(39);
+        assert!(!timer.irq());
+        timer.tick(1);
+        assert!(timer.irq());
+        timer.read(REG_STATUS);
+
+        timer.write(REG_CONTROL, CONTROL_RUN | CONTROL_IRQ);
+        timer.tick(60);
+        timer.write(REG_CONTROL, CONTROL_RUN | CONTROL_PERIODIC);
+        assert_eq!(timer.read(REG_COUNTER_LO), 40);
+
+        // clearing and setting RUN starts over
+        timer.write(REG_CONTROL, CONTROL_PERIODIC);
+        timer.write(REG_CONTROL, CONTROL_RUN | CONTROL_PERIODIC);
+        assert_eq!(timer.read(REG_COUNTER_LO), 100);
+    }
+
+    #[test]
+    fn stop() {
+        let mut timer = Timer::new();
+        start(&mut timer, 10, CONTROL_PERIODIC | CONTROL_IRQ);
+        timer.write(REG_CONTROL, CONTROL_PERIODIC | CONTROL_IRQ);
+        timer.tick(100);
+        assert!(!timer.irq());
+    }
+}
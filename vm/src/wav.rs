@@ -0,0 +1,99 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+const CHANNELS: u16 = 1;
+const BITS: u16 = 16;
+const BLOCK_ALIGN: u16 = CHANNELS * BITS / 8;
+const HEADER_LEN: u32 = 44;
+
+// Writes signed 16-bit mono samples as a PCM WAV file as they come. The sizes in the header
+// are only known at the end, so they are filled in by `finish`.
+pub struct Writer<W: Write + Seek> {
+    out: W,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> Writer<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_LEN - 8).to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16_u32.to_le_bytes());
+        header.extend_from_slice(&1_u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&CHANNELS.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * BLOCK_ALIGN as u32).to_le_bytes());
+        header.extend_from_slice(&BLOCK_ALIGN.to_le_bytes());
+        header.extend_from_slice(&BITS.to_le_bytes());
+
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0_u32.to_le_bytes());
+        out.write_all(&header)?;
+
+        Ok(Self { out, data_len: 0 })
+    }
+
+    // The sizes in the header are 32-bit, so a file cannot hold more than 4 GiB.
+    pub fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        let len = samples.len() as u64 * BLOCK_ALIGN as u64;
+        let data_len = self.data_len as u64 + len;
+        if data_len > (u32::MAX - HEADER_LEN) as u64 {
+            return Err(io::Error::other("the recording is too long for a WAV file"));
+        }
+
+        let mut data = Vec::with_capacity(len as usize);
+        for sample in samples {
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.out.write_all(&data)?;
+        self.data_len = data_len as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out
+            .write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+        self.out.write_all(&self.data_len.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod wav_test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn header() {
+        let mut writer = Writer::new(Cursor::new(Vec::new()), 44100).unwrap();
+        writer.write(&[0, 1]).unwrap();
+        writer.write(&[-1]).unwrap();
+        let wav = writer.finish().unwrap().into_inner();
+
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(wav[0..4], *b"RIFF");
+        assert_eq!(wav[4..8], 42_u32.to_le_bytes());
+        assert_eq!(wav[8..16], *b"WAVEfmt ");
+        assert_eq!(wav[22..24], [1, 0]); // mono
+        assert_eq!(wav[24..28], 44100_u32.to_le_bytes());
+        assert_eq!(wav[28..32], 88200_u32.to_le_bytes());
+        assert_eq!(wav[36..40], *b"data");
+        assert_eq!(wav[40..44], 6_u32.to_le_bytes());
+        assert_eq!(wav[44..], [0, 0, 1, 0, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn too_long() {
+        let mut writer = Writer::new(Cursor::new(Vec::new()), 44100).unwrap();
+        writer.data_len = u32::MAX - HEADER_LEN - 2;
+        writer.write(&[0]).unwrap();
+        assert!(writer.write(&[0]).is_err());
+        assert_eq!(writer.data_len, u32::MAX - HEADER_LEN);
+    }
+}